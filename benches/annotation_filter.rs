@@ -0,0 +1,80 @@
+//! Compares `/get_filtered_annotations` lookups through the `AnnotationStore` indexes against the
+//! full scan the handler used to do. Run with `cargo bench --bench annotation_filter`.
+use author_console::models::{AlvariumAnnotation, Annotation, ReadingId};
+use author_console::store::{AnnotationStorage, AnnotationStore, AnnotationStoreFilter};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const ANNOTATIONS: u64 = 500_000;
+const ITERATIONS: u32 = 20;
+
+fn make_annotation(i: u64) -> Annotation {
+    let mut annotation = AlvariumAnnotation::default();
+    annotation.payload.iss = format!("host-{}", i % 50);
+    annotation.payload.sub = format!("sub-{}", i % 10_000);
+    annotation.payload.iat = 1_602_350_950_000 + i;
+    annotation.payload.jti = format!("jti-{}", i);
+    annotation.payload.ann = ["pki", "tls", "tpm", "source"][(i % 4) as usize].to_string();
+    annotation.payload.avl = 1.0;
+
+    Annotation::new()
+        .with_reading_id(ReadingId(format!("reading-{}", i / 4)))
+        .with_annotation(annotation)
+}
+
+/// The per field scan over every annotation that the handler did before the indexes existed
+fn scan<'a>(annotations: &'a HashMap<ReadingId, Vec<Annotation>>, filter: &AnnotationStoreFilter) -> Vec<&'a Annotation> {
+    let mut found = Vec::new();
+    for ann_vec in annotations.values() {
+        found.extend(ann_vec.iter().filter(|ann| filter.iss.as_ref() == Some(&ann.annotation.payload.iss)));
+        found.extend(ann_vec.iter().filter(|ann| filter.ann.as_ref() == Some(&ann.annotation.payload.ann)));
+        found.extend(ann_vec.iter().filter(|ann| filter.iat.as_ref() == Some(&ann.annotation.payload.iat)));
+        found.extend(ann_vec.iter().filter(|ann| filter.jti.as_ref() == Some(&ann.annotation.payload.jti)));
+        found.extend(ann_vec.iter().filter(|ann| filter.sub.as_ref() == Some(&ann.annotation.payload.sub)));
+    }
+    found
+}
+
+fn time<F: FnMut() -> usize>(mut f: F) -> (Duration, usize) {
+    let start = Instant::now();
+    let mut found = 0;
+    for _ in 0..ITERATIONS {
+        found = f();
+    }
+    (start.elapsed() / ITERATIONS, found)
+}
+
+fn main() {
+    println!("Loading {} annotations...", ANNOTATIONS);
+    let mut store = AnnotationStore::new();
+    let mut scanned: HashMap<ReadingId, Vec<Annotation>> = HashMap::new();
+    for i in 0..ANNOTATIONS {
+        let annotation = make_annotation(i);
+        let reading_id = annotation.get_reading_id().clone();
+        store.insert(&reading_id, annotation).unwrap();
+        scanned.entry(reading_id).or_default().push(make_annotation(i));
+    }
+
+    let filters = vec![
+        ("jti", AnnotationStoreFilter { jti: Some("jti-4242".to_string()), ..Default::default() }),
+        ("sub", AnnotationStoreFilter { sub: Some("sub-42".to_string()), ..Default::default() }),
+        ("iat", AnnotationStoreFilter { iat: Some(1_602_350_954_242), ..Default::default() }),
+        ("iss", AnnotationStoreFilter { iss: Some("host-7".to_string()), ..Default::default() }),
+    ];
+
+    println!("{:<8}{:>10}{:>16}{:>16}{:>10}", "filter", "matches", "scan", "indexed", "speedup");
+    for (name, filter) in filters {
+        let (scan_time, scan_found) = time(|| scan(&scanned, &filter).len());
+        let (index_time, index_found) = time(|| store.filter(&filter, None).count());
+        assert_eq!(scan_found, index_found);
+
+        println!(
+            "{:<8}{:>10}{:>16?}{:>16?}{:>9.0}x",
+            name,
+            index_found,
+            scan_time,
+            index_time,
+            scan_time.as_secs_f64() / index_time.as_secs_f64()
+        );
+    }
+}
@@ -1,7 +1,8 @@
 use hyper::{service::{make_service_fn, service_fn}, Body, Method, Request, Response, Server, StatusCode};
 
-use crate::streams::ChannelAuthor;
-use crate::store::{AnnotationStore, ReadingStore};
+use crate::streams::{AuthorHandle, IngestStats, MessageIngestor, RetrieverStatus};
+use crate::models::ConfidencePolicies;
+use crate::store::{AnnotationStorage, DeadLetterStore, ReadingStorage};
 
 use std::{net::SocketAddr, sync::{Arc, Mutex}};
 use crate::http::*;
@@ -11,9 +12,14 @@ type GenericError = Box<dyn std::error::Error + Send + Sync>;
 
 pub async fn start(
     port: u16,
-    author: Arc<Mutex<ChannelAuthor>>,
-    annotation_store: Arc<Mutex<AnnotationStore>>,
-    reading_store: Arc<Mutex<ReadingStore>>
+    author: AuthorHandle,
+    annotation_store: Arc<Mutex<dyn AnnotationStorage>>,
+    reading_store: Arc<Mutex<dyn ReadingStorage>>,
+    dead_letter_store: Arc<Mutex<DeadLetterStore>>,
+    ingest_stats: Arc<Mutex<IngestStats>>,
+    ingestor: Arc<MessageIngestor>,
+    retriever_status: Arc<Mutex<RetrieverStatus>>,
+    confidence_policies: Arc<ConfidencePolicies>
 ) -> Result<(), GenericError> {
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
 
@@ -21,13 +27,23 @@ pub async fn start(
         let author = author.clone();
         let annotation_store = annotation_store.clone();
         let reading_store = reading_store.clone();
+        let dead_letter_store = dead_letter_store.clone();
+        let ingest_stats = ingest_stats.clone();
+        let ingestor = ingestor.clone();
+        let retriever_status = retriever_status.clone();
+        let confidence_policies = confidence_policies.clone();
         async {
             Ok::<_, GenericError>(service_fn(move |req| {
                 responder(
                     req,
                     author.clone(),
                     annotation_store.clone(),
-                    reading_store.clone()
+                    reading_store.clone(),
+                    dead_letter_store.clone(),
+                    ingest_stats.clone(),
+                    ingestor.clone(),
+                    retriever_status.clone(),
+                    confidence_policies.clone()
                 )
             }))
         }
@@ -44,14 +60,26 @@ pub async fn start(
 
 async fn responder(
     req: Request<Body>,
-    author: Arc<Mutex<ChannelAuthor>>,
-    annotation_store: Arc<Mutex<AnnotationStore>>,
-    reading_store: Arc<Mutex<ReadingStore>>,
+    author: AuthorHandle,
+    annotation_store: Arc<Mutex<dyn AnnotationStorage>>,
+    reading_store: Arc<Mutex<dyn ReadingStorage>>,
+    dead_letter_store: Arc<Mutex<DeadLetterStore>>,
+    ingest_stats: Arc<Mutex<IngestStats>>,
+    ingestor: Arc<MessageIngestor>,
+    retriever_status: Arc<Mutex<RetrieverStatus>>,
+    confidence_policies: Arc<ConfidencePolicies>,
 ) -> Result<Response<Body>, GenericError> {
     match req.method() {
         &Method::OPTIONS => preflight_response().await,
         _ => match (req.method(), req.uri().path()) {
             (&Method::POST, "/subscribe") => subscribe_response(req, author).await,
+            (&Method::POST, "/subscribe_batch") => subscribe_batch_response(req, author).await,
+            (&Method::POST, "/revoke") => revoke_response(req, author).await,
+            (&Method::POST, "/create_psk_group") => create_psk_group_response(req, author).await,
+            (&Method::GET, "/get_psk_groups") => psk_groups_response(author).await,
+            (&Method::POST, "/psk_keyload") => psk_keyload_response(req, author).await,
+            (&Method::POST, "/send_signed_packet") => send_packet_response(req, author, true).await,
+            (&Method::POST, "/send_tagged_packet") => send_packet_response(req, author, false).await,
             (&Method::GET, "/get_channel_address") => {
                 channel_address_response(author).await
             }
@@ -61,15 +89,36 @@ async fn responder(
             (&Method::POST, "/get_readings") => {
                 readings_response(req, reading_store).await
             }
+            (&Method::GET, path) if path.starts_with("/readings/") => {
+                reading_response(path.trim_start_matches("/readings/").to_string(), reading_store).await
+            }
             (&Method::POST, "/get_annotations") => {
                 annotations_response(req, annotation_store).await
             }
             (&Method::POST, "/get_confidence_score") => {
-                confidence_score_response(req, annotation_store).await
+                confidence_score_response(req, annotation_store, confidence_policies).await
             }
             (&Method::POST, "/get_filtered_annotations") => {
                 filter_annotations_response(req, annotation_store).await
             }
+            (&Method::GET, "/get_ingest_stats") => {
+                ingest_stats_response(ingest_stats).await
+            }
+            (&Method::GET, "/get_retriever_status") => {
+                retriever_status_response(retriever_status).await
+            }
+            (&Method::GET, "/get_dead_letters") | (&Method::POST, "/get_dead_letters") => {
+                dead_letters_response(req, dead_letter_store).await
+            }
+            (&Method::POST, "/get_dead_letter") => {
+                dead_letter_response(req, dead_letter_store).await
+            }
+            (&Method::POST, "/reprocess_dead_letters") => {
+                reprocess_dead_letters_response(req, dead_letter_store, ingestor).await
+            }
+            (&Method::POST, "/purge_dead_letters") => {
+                purge_dead_letters_response(req, dead_letter_store).await
+            }
             _ => {
                 Ok(Response::builder()
                     .status(StatusCode::NOT_FOUND)
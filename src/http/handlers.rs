@@ -1,8 +1,10 @@
 use hyper::{Request, Body, Response, StatusCode, header};
-use crate::streams::ChannelAuthor;
-use crate::models::{SubscriptionRequest, SensorId, ReadingId};
+use crate::streams::{AuthorHandle, IngestStats, MessageIngestor, RetrieverStatus};
+use crate::models::{SubscriptionRequest, RevokeRequest, PskGroupRequest, PskKeyloadRequest, PacketRequest, SensorId, ReadingId, Reading, Annotation,
+                    ConfidencePolicies};
 use std::sync::{Mutex, Arc};
-use crate::store::{ReadingStore, ReadingStoreFilterId, AnnotationStoreFilterId, AnnotationStore, AnnotationStoreFilter};
+use crate::store::{ReadingStorage, ReadingStoreFilterId, AnnotationStoreFilterId, AnnotationStorage, AnnotationFilterRequest,
+                   DeadLetterStore, DeadLetterStoreFilterId, Page, PageRequest, lock};
 
 type GenericError = Box<dyn std::error::Error + Send + Sync>;
 
@@ -19,7 +21,7 @@ pub async fn preflight_response(
 
 pub async fn subscribe_response(
     req: Request<Body>,
-    author: Arc<Mutex<ChannelAuthor>>,
+    author: AuthorHandle,
 ) -> Result<Response<Body>, GenericError> {
     let data = hyper::body::to_bytes(req.into_body()).await?;
 
@@ -27,8 +29,7 @@ pub async fn subscribe_response(
     let json_data: serde_json::Result<SubscriptionRequest> = serde_json::from_slice(&data);
     match json_data {
         Ok(sub_req) => {
-            let mut author = author.lock().unwrap();
-            match author.subscribe(&sub_req.msgid, &hex::decode(sub_req.pk)?) {
+            match author.subscribe(sub_req.msgid, hex::decode(sub_req.pk)?).await {
                 Ok(keyload_link) => {
                     println!("Processed subscription, returning keyload link...");
                     response = Response::builder()
@@ -59,12 +60,251 @@ pub async fn subscribe_response(
     Ok(response)
 }
 
+pub async fn subscribe_batch_response(
+    req: Request<Body>,
+    author: AuthorHandle,
+) -> Result<Response<Body>, GenericError> {
+    let data = hyper::body::to_bytes(req.into_body()).await?;
+
+    let response;
+    let json_data: serde_json::Result<Vec<SubscriptionRequest>> = serde_json::from_slice(&data);
+    match json_data {
+        Ok(sub_reqs) => {
+            let count = sub_reqs.len();
+            match author.subscribe_batch(sub_reqs).await {
+                Ok(batch_response) => {
+                    println!("Processed {} subscriptions, returning keyload link...", count);
+                    response = Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from(serde_json::to_string(&batch_response)?))?;
+                },
+                Err(_) => {
+                    response = Response::builder()
+                        .status(500)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from("Error while subscribing"))?;
+                }
+            }
+        },
+        Err(e) => {
+            println!("Error in formatting: {:?}", e);
+            response = Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("Malformed json request"))?;
+        }
+    }
+
+    Ok(response)
+}
+
+pub async fn revoke_response(
+    req: Request<Body>,
+    author: AuthorHandle,
+) -> Result<Response<Body>, GenericError> {
+    let data = hyper::body::to_bytes(req.into_body()).await?;
+
+    let response;
+    let json_data: serde_json::Result<RevokeRequest> = serde_json::from_slice(&data);
+    match json_data {
+        Ok(revoke_req) => {
+            match author.revoke(hex::decode(revoke_req.pk)?).await {
+                Ok(keyload_link) => {
+                    println!("Revoked subscriber, returning keyload link...");
+                    response = Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from("Subscriber revoked, keyload link: ".to_owned() + &keyload_link.to_string()))?;
+                },
+                Err(e) => {
+                    response = Response::builder()
+                        .status(500)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from("Error while revoking subscriber: ".to_owned() + &e.to_string()))?;
+                }
+            }
+        },
+        Err(e) => {
+            println!("Error in formatting: {:?}", e);
+            response = Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("Malformed json request"))?;
+        }
+    }
+
+    Ok(response)
+}
+
+pub async fn create_psk_group_response(
+    req: Request<Body>,
+    author: AuthorHandle,
+) -> Result<Response<Body>, GenericError> {
+    let data = hyper::body::to_bytes(req.into_body()).await?;
+
+    let response;
+    let json_data: serde_json::Result<PskGroupRequest> = serde_json::from_slice(&data);
+    match json_data {
+        Ok(group_req) => {
+            match author.create_psk_group(group_req.name).await {
+                Ok(psk_group) => {
+                    println!("Created PSK group {}, returning keyload link...", psk_group.name);
+                    response = Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from(serde_json::to_string(&psk_group)?))?;
+                },
+                Err(e) => {
+                    response = Response::builder()
+                        .status(500)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from("Error while creating PSK group: ".to_owned() + &e.to_string()))?;
+                }
+            }
+        },
+        Err(e) => {
+            println!("Error in formatting: {:?}", e);
+            response = Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("Malformed json request"))?;
+        }
+    }
+
+    Ok(response)
+}
+
+pub async fn psk_groups_response(
+    author: AuthorHandle,
+) -> Result<Response<Body>, GenericError> {
+    let response;
+
+    match author.get_psk_groups().await {
+        Ok(psk_groups) => {
+            response = Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from(serde_json::to_string(&psk_groups)?))?;
+        },
+        Err(_e) => {
+            response = Response::builder()
+                .status(500)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("Error while getting PSK groups"))?;
+        }
+    }
+
+    Ok(response)
+}
+
+pub async fn psk_keyload_response(
+    req: Request<Body>,
+    author: AuthorHandle,
+) -> Result<Response<Body>, GenericError> {
+    let data = hyper::body::to_bytes(req.into_body()).await?;
+
+    let response;
+    let json_data: serde_json::Result<PskKeyloadRequest> = serde_json::from_slice(&data);
+    match json_data {
+        Ok(keyload_req) => {
+            match author.send_psk_keyload(keyload_req.names).await {
+                Ok(keyload_link) => {
+                    println!("Sent PSK keyload, returning keyload link...");
+                    response = Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from("PSK keyload sent, keyload link: ".to_owned() + &keyload_link.to_string()))?;
+                },
+                Err(e) => {
+                    response = Response::builder()
+                        .status(500)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from("Error while sending PSK keyload: ".to_owned() + &e.to_string()))?;
+                }
+            }
+        },
+        Err(e) => {
+            println!("Error in formatting: {:?}", e);
+            response = Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("Malformed json request"))?;
+        }
+    }
+
+    Ok(response)
+}
+
+pub async fn send_packet_response(
+    req: Request<Body>,
+    author: AuthorHandle,
+    signed: bool,
+) -> Result<Response<Body>, GenericError> {
+    let data = hyper::body::to_bytes(req.into_body()).await?;
+
+    let response;
+    let json_data: serde_json::Result<PacketRequest> = serde_json::from_slice(&data);
+    match json_data {
+        Ok(packet_req) => {
+            let public_payload = packet_req.public_payload.into_bytes();
+            let masked_payload = packet_req.masked_payload.into_bytes();
+            let sent = if signed {
+                author.send_signed_packet(packet_req.link_to, public_payload, masked_payload).await
+            } else {
+                author.send_tagged_packet(packet_req.link_to, public_payload, masked_payload).await
+            };
+
+            match sent {
+                Ok(msg_link) => {
+                    println!("Sent {} packet, returning message link...", if signed {"signed"} else {"tagged"});
+                    response = Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from("Packet sent, message link: ".to_owned() + &msg_link.to_string()))?;
+                },
+                Err(e) => {
+                    response = Response::builder()
+                        .status(500)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from("Error while sending packet: ".to_owned() + &e.to_string()))?;
+                }
+            }
+        },
+        Err(e) => {
+            println!("Error in formatting: {:?}", e);
+            response = Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("Malformed json request"))?;
+        }
+    }
+
+    Ok(response)
+}
+
 pub async fn channel_address_response(
-    author: Arc<Mutex<ChannelAuthor>>,
+    author: AuthorHandle,
 ) -> Result<Response<Body>, GenericError> {
     let response;
 
-    let author = author.lock().unwrap();
     match author.get_channel_address() {
         Ok(channel_address) => {
             response = Response::builder()
@@ -86,11 +326,10 @@ pub async fn channel_address_response(
 }
 
 pub async fn announcement_id_response(
-    author: Arc<Mutex<ChannelAuthor>>,
+    author: AuthorHandle,
 ) -> Result<Response<Body>, GenericError> {
     let response;
 
-    let author = author.lock().unwrap();
     match author.get_announcement_id() {
         Ok(announcement_id) => {
             response = Response::builder()
@@ -114,37 +353,42 @@ pub async fn announcement_id_response(
 
 pub async fn readings_response(
     req: Request<Body>,
-    reading_store: Arc<Mutex<ReadingStore>>
+    reading_store: Arc<Mutex<dyn ReadingStorage>>
 ) -> Result<Response<Body>, GenericError> {
     let data = hyper::body::to_bytes(req.into_body()).await?;
 
     let response;
     let sensor_id: serde_json::Result<ReadingStoreFilterId> = serde_json::from_slice(&data);
     match sensor_id {
-        Ok(sensor_id) => {
-            match reading_store.lock() {
-                Ok(mut reading_store) => {
-                    let sensor_id = SensorId(sensor_id.get_sensor_id());
-                    let readings = reading_store.get(&sensor_id);
-
-                    if readings.is_ok() {
-                        response = Response::builder()
-                            .status(StatusCode::OK)
-                            .header(header::CONTENT_TYPE, "application/json")
-                            .header("Access-Control-Allow-Origin", "*")
-                            .body(Body::from(serde_json::to_string(readings.unwrap())?))?;
-                    } else {
-                        response = Response::builder()
-                            .status(500)
-                            .header(header::CONTENT_TYPE, "application/json")
-                            .header("Access-Control-Allow-Origin", "*")
-                            .body(Body::from("Error while fetching readings for ".to_owned() +
-                                sensor_id.0.as_str()))?;
-                    }
-                },
-                _ => {
-                    response = busy()
-                }
+        Ok(sensor_id_filter) => {
+            let mut reading_store = lock(&reading_store);
+            let sensor_id = SensorId(sensor_id_filter.get_sensor_id());
+            let page = sensor_id_filter.get_page();
+            let readings = reading_store.iter_range(
+                &sensor_id,
+                sensor_id_filter.get_from(),
+                sensor_id_filter.get_to(),
+                page.get_cursor()
+            );
+
+            if readings.is_ok() {
+                let readings: Page<&Reading> = Page::collect(readings.unwrap()
+                    .filter(|(_, reading)| sensor_id_filter.get_public_payload()
+                        .map_or(true, |filter| reading.get_metadata().matches_public_payload(filter))),
+                    page.get_limit());
+
+                response = Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(serde_json::to_string(&readings)?))?;
+            } else {
+                response = Response::builder()
+                    .status(500)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from("Error while fetching readings for ".to_owned() +
+                        sensor_id.0.as_str()))?;
             }
         },
 
@@ -160,9 +404,48 @@ pub async fn readings_response(
     Ok(response)
 }
 
+pub async fn reading_response(
+    path_segment: String,
+    reading_store: Arc<Mutex<dyn ReadingStorage>>
+) -> Result<Response<Body>, GenericError> {
+    let response;
+
+    let reading_id = match percent_decode(&path_segment) {
+        Some(reading_id) => ReadingId(reading_id),
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("Malformed reading id: ".to_owned() + &path_segment))?)
+        }
+    };
+
+    let mut reading_store = lock(&reading_store);
+    match reading_store.get_by_reading_id(&reading_id) {
+        Ok(readings) => {
+            response = Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from(serde_json::to_string(&readings)?))?;
+        },
+        Err(_e) => {
+            response = Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("No reading found for ".to_owned() + reading_id.0.as_str()))?;
+        }
+    }
+
+    Ok(response)
+}
+
 pub async fn confidence_score_response(
     req: Request<Body>,
-    annotation_store: Arc<Mutex<AnnotationStore>>
+    annotation_store: Arc<Mutex<dyn AnnotationStorage>>,
+    confidence_policies: Arc<ConfidencePolicies>
 ) -> Result<Response<Body>, GenericError> {
     let data = hyper::body::to_bytes(req.into_body()).await?;
 
@@ -170,19 +453,14 @@ pub async fn confidence_score_response(
     let annotation_id: serde_json::Result<AnnotationStoreFilterId> = serde_json::from_slice(&data);
     match annotation_id {
         Ok(annotation_id) => {
-            match annotation_store.lock() {
-                Ok(mut annotation_store) => {
+            match confidence_policies.get(annotation_id.get_policy()) {
+                Ok(policy) => {
+                    let mut annotation_store = lock(&annotation_store);
                     let reading_id = ReadingId(annotation_id.get_reading_id());
                     let annotations = annotation_store.get(&reading_id);
 
-                    if annotations.is_ok() {
-                        let mut confidence_score = 0 as f64;
-                        annotations.unwrap()
-                            .iter()
-                            .map(|ann| {
-                                confidence_score += ann.get_confidence_score()
-                            })
-                            .for_each(drop);
+                    if let Ok(annotations) = annotations {
+                        let confidence_score = policy.score(&annotations);
 
                         response = Response::builder()
                             .status(StatusCode::OK)
@@ -199,8 +477,12 @@ pub async fn confidence_score_response(
                                 reading_id.0.as_str()))?;
                     }
                 },
-                _ => {
-                    response = busy()
+                Err(e) => {
+                    response = Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from(e.to_string()))?;
                 }
             }
         },
@@ -219,7 +501,7 @@ pub async fn confidence_score_response(
 
 pub async fn annotations_response(
     req: Request<Body>,
-    annotation_store: Arc<Mutex<AnnotationStore>>
+    annotation_store: Arc<Mutex<dyn AnnotationStorage>>
 ) -> Result<Response<Body>, GenericError> {
     let data = hyper::body::to_bytes(req.into_body()).await?;
 
@@ -227,29 +509,34 @@ pub async fn annotations_response(
     let annotation_id: serde_json::Result<AnnotationStoreFilterId> = serde_json::from_slice(&data);
     match annotation_id {
         Ok(annotation_id) => {
-            match annotation_store.lock() {
-                Ok(mut annotation_store) => {
-                    let reading_id = ReadingId(annotation_id.get_reading_id());
-                    let annotations = annotation_store.get(&reading_id);
-
-                    if annotations.is_ok() {
-                        response = Response::builder()
-                            .status(StatusCode::OK)
-                            .header(header::CONTENT_TYPE, "application/json")
-                            .header("Access-Control-Allow-Origin", "*")
-                            .body(Body::from(serde_json::to_vec(annotations.unwrap())?))?;
-                    } else {
-                        response = Response::builder()
-                            .status(500)
-                            .header(header::CONTENT_TYPE, "application/json")
-                            .header("Access-Control-Allow-Origin", "*")
-                            .body(Body::from("Error while fetching annotations for ".to_owned() +
-                                reading_id.0.as_str()))?;
-                    }
-                },
-                _ => {
-                    response = busy()
-                }
+            let mut annotation_store = lock(&annotation_store);
+            let reading_id = ReadingId(annotation_id.get_reading_id());
+            let page = annotation_id.get_page();
+            let annotations = annotation_store.iter_range(
+                &reading_id,
+                annotation_id.get_from(),
+                annotation_id.get_to(),
+                page.get_cursor()
+            );
+
+            if annotations.is_ok() {
+                let annotations: Page<&Annotation> = Page::collect(annotations.unwrap()
+                    .filter(|(_, annotation)| annotation_id.get_public_payload()
+                        .map_or(true, |filter| annotation.get_metadata().matches_public_payload(filter))),
+                    page.get_limit());
+
+                response = Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(serde_json::to_vec(&annotations)?))?;
+            } else {
+                response = Response::builder()
+                    .status(500)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from("Error while fetching annotations for ".to_owned() +
+                        reading_id.0.as_str()))?;
             }
         },
 
@@ -267,56 +554,32 @@ pub async fn annotations_response(
 
 pub async fn filter_annotations_response(
     req: Request<Body>,
-    annotation_store: Arc<Mutex<AnnotationStore>>
+    annotation_store: Arc<Mutex<dyn AnnotationStorage>>
 ) -> Result<Response<Body>, GenericError> {
     let data = hyper::body::to_bytes(req.into_body()).await?;
 
     let response;
-    let filters: serde_json::Result<AnnotationStoreFilter> = serde_json::from_slice(&data);
+    let filters: serde_json::Result<AnnotationFilterRequest> = serde_json::from_slice(&data);
 
     match filters {
         Ok(filters) => {
-            match annotation_store.lock() {
-                Ok(mut annotation_store) => {
-                    let mut annotations = Vec::new();
-
-                    let mapped = annotation_store.iter().map(|ann_iter| {
-                        ann_iter.for_each(|(_id, ann_vec)| {
-                            annotations.extend(ann_vec.into_iter()
-                                .filter(|ann| filters.iss.is_some() &&
-                                    &ann.annotation.payload.iss == filters.iss.as_ref().unwrap()));
-                            annotations.extend(ann_vec.into_iter()
-                                .filter(|ann| filters.ann.is_some() &&
-                                    &ann.annotation.payload.ann == filters.ann.as_ref().unwrap()));
-                            annotations.extend(ann_vec.into_iter()
-                                .filter(|ann| filters.iat.is_some() &&
-                                    &ann.annotation.payload.iat == filters.iat.as_ref().unwrap()));
-                            annotations.extend(ann_vec.into_iter()
-                                .filter(|ann| filters.jti.is_some() &&
-                                    &ann.annotation.payload.jti == filters.jti.as_ref().unwrap()));
-                            annotations.extend(ann_vec.into_iter()
-                                .filter(|ann| filters.sub.is_some() &&
-                                    &ann.annotation.payload.sub == filters.sub.as_ref().unwrap()));
-                        })
-                    });
-
-                    if mapped.is_ok() {
-                        response = Response::builder()
-                            .status(StatusCode::OK)
-                            .header(header::CONTENT_TYPE, "application/json")
-                            .header("Access-Control-Allow-Origin", "*")
-                            .body(Body::from(serde_json::to_vec(&annotations)?))?;
-                    } else {
-                        response = Response::builder()
-                            .status(500)
-                            .header(header::CONTENT_TYPE, "application/json")
-                            .header("Access-Control-Allow-Origin", "*")
-                            .body(Body::from("Error while mapping annotations"))?;
-                    }
-                },
-                _ => {
-                    response = busy()
-                }
+            let mut annotation_store = lock(&annotation_store);
+            let page = filters.get_page();
+            let annotations = annotation_store.filter(filters.get_filter(), page.get_cursor());
+
+            if let Ok(annotations) = annotations {
+                let annotations = Page::collect(annotations, page.get_limit());
+                response = Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(serde_json::to_vec(&annotations)?))?;
+            } else {
+                response = Response::builder()
+                    .status(500)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from("Error while mapping annotations"))?;
             }
         },
 
@@ -332,11 +595,216 @@ pub async fn filter_annotations_response(
     Ok(response)
 }
 
-fn busy() -> Response<Body>{
-    Response::builder()
-        .status(500)
+pub async fn ingest_stats_response(
+    ingest_stats: Arc<Mutex<IngestStats>>
+) -> Result<Response<Body>, GenericError> {
+    let response;
+
+    let ingest_stats = lock(&ingest_stats);
+    response = Response::builder()
+        .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json")
         .header("Access-Control-Allow-Origin", "*")
-        .body(Body::from("Service is busy"))
-        .unwrap()
+        .body(Body::from(serde_json::to_string(&*ingest_stats)?))?;
+
+    Ok(response)
+}
+
+pub async fn retriever_status_response(
+    retriever_status: Arc<Mutex<RetrieverStatus>>
+) -> Result<Response<Body>, GenericError> {
+    let response;
+
+    let retriever_status = lock(&retriever_status);
+    response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(Body::from(serde_json::to_string(&*retriever_status)?))?;
+
+    Ok(response)
+}
+
+pub async fn dead_letters_response(
+    req: Request<Body>,
+    dead_letter_store: Arc<Mutex<DeadLetterStore>>
+) -> Result<Response<Body>, GenericError> {
+    let data = hyper::body::to_bytes(req.into_body()).await?;
+
+    let response;
+    // The first page can still be fetched with a plain GET, without a body
+    let page: serde_json::Result<PageRequest> = if data.is_empty() {
+        Ok(PageRequest::default())
+    } else {
+        serde_json::from_slice(&data)
+    };
+
+    match page {
+        Ok(page) => {
+            let dead_letter_store = lock(&dead_letter_store);
+            let dead_letters = Page::collect(dead_letter_store.iter_after(page.get_cursor()), page.get_limit());
+            response = Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from(serde_json::to_string(&dead_letters)?))?;
+        },
+        Err(_) => {
+            response = Response::builder()
+                .status(500)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("Error while reading page fields"))?;
+        }
+    }
+
+    Ok(response)
+}
+
+pub async fn dead_letter_response(
+    req: Request<Body>,
+    dead_letter_store: Arc<Mutex<DeadLetterStore>>
+) -> Result<Response<Body>, GenericError> {
+    let data = hyper::body::to_bytes(req.into_body()).await?;
+
+    let response;
+    let dead_letter_id: serde_json::Result<DeadLetterStoreFilterId> = serde_json::from_slice(&data);
+    match dead_letter_id {
+        Ok(dead_letter_id) if dead_letter_id.get_id().is_some() => {
+            let dead_letter_store = lock(&dead_letter_store);
+            let id = dead_letter_id.get_id().unwrap();
+            match dead_letter_store.get(id) {
+                Ok(dead_letter) => {
+                    response = Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from(serde_json::to_string(dead_letter)?))?;
+                },
+                Err(_) => {
+                    response = Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from("No dead letter with id ".to_owned() + &id.to_string()))?;
+                }
+            }
+        },
+
+        _ => {
+            response = Response::builder()
+                .status(500)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("Error while reading id field"))?;
+        }
+    }
+
+    Ok(response)
+}
+
+pub async fn reprocess_dead_letters_response(
+    req: Request<Body>,
+    dead_letter_store: Arc<Mutex<DeadLetterStore>>,
+    ingestor: Arc<MessageIngestor>
+) -> Result<Response<Body>, GenericError> {
+    let data = hyper::body::to_bytes(req.into_body()).await?;
+
+    let response;
+    let dead_letter_id: serde_json::Result<DeadLetterStoreFilterId> = serde_json::from_slice(&data);
+    match dead_letter_id {
+        Ok(dead_letter_id) => {
+            // Re-process the given dead letter, or all of them if no id is provided
+            let ids = match dead_letter_id.get_id() {
+                Some(id) => vec![id],
+                None => lock(&dead_letter_store).ids()
+            };
+
+            let mut reprocessed = Vec::new();
+            let mut failed = Vec::new();
+            for id in ids {
+                match ingestor.reprocess(id) {
+                    Ok(_) => reprocessed.push(id),
+                    Err(e) => failed.push(serde_json::json!({ "id": id, "error": e.to_string() }))
+                }
+            }
+
+            response = Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from(serde_json::json!({
+                    "reprocessed": reprocessed,
+                    "failed": failed
+                }).to_string()))?;
+        },
+
+        Err(_e) => {
+            response = Response::builder()
+                .status(500)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("Error while reading id field"))?;
+        }
+    }
+
+    Ok(response)
+}
+
+pub async fn purge_dead_letters_response(
+    req: Request<Body>,
+    dead_letter_store: Arc<Mutex<DeadLetterStore>>
+) -> Result<Response<Body>, GenericError> {
+    let data = hyper::body::to_bytes(req.into_body()).await?;
+
+    let response;
+    let dead_letter_id: serde_json::Result<DeadLetterStoreFilterId> = serde_json::from_slice(&data);
+    match dead_letter_id {
+        Ok(dead_letter_id) => {
+            let mut dead_letter_store = lock(&dead_letter_store);
+            // Purge the given dead letter, or all of them if no id is provided
+            let purged = match dead_letter_id.get_id() {
+                Some(id) => dead_letter_store.remove(id).map(|_| 1).unwrap_or(0),
+                None => dead_letter_store.purge()?
+            };
+
+            response = Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("{ \"purged\": ".to_owned() + &purged.to_string() + " }"))?;
+        },
+
+        Err(_e) => {
+            response = Response::builder()
+                .status(500)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("Error while reading id field"))?;
+        }
+    }
+
+    Ok(response)
+}
+
+/// Decodes the `%XX` escapes of a URL path segment. Returns None if an escape is not followed by
+/// two hex digits or the decoded bytes are not valid utf-8.
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = segment.get(i + 1..i + 3)?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None
+            }
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
 }
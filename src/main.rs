@@ -1,10 +1,19 @@
-use anyhow::Result;
-use author_console::store::{AnnotationStore, ReadingStore};
-use author_console::streams::{ChannelAuthor, MessageRetriever};
+use anyhow::{Result, anyhow};
+use author_console::store::{
+    open_sled, AnnotationStorage, AnnotationStore, Compactor, DeadLetterStore, Journal, ReadingStorage, ReadingStore,
+    RetentionPolicy, SledAnnotationStore, SledReadingStore
+};
+use author_console::streams::{
+    AuthorHandle, AuthorStateFile, ChannelAuthor, DuplicatePolicy, IngestStats, MessageIngestor, MessageRetriever,
+    PollingConfig, SignerPolicy
+};
+use author_console::models::ConfidencePolicies;
 use author_console::http::api_server;
 
 use std::sync::{Arc, Mutex};
 use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
 use rand::Rng;
 
 #[tokio::main]
@@ -25,26 +34,257 @@ async fn main() -> Result<()> {
     let local_pow = config["local_pow"].as_bool().unwrap();
     let port = config["api_port"].as_u64().unwrap() as u16;
 
+    let state_file = match config["state_file"].as_str() {
+        Some(path) => {
+            let password = config["state_password"].as_str()
+                .ok_or_else(|| anyhow!("state_password must be set when state_file is set"))?;
+            Some(AuthorStateFile::new(path, password))
+        },
+        None => None
+    };
+    let state_save_interval = config["state_save_interval"].as_u64().unwrap_or(60);
+    if state_save_interval == 0 {
+        return Err(anyhow!("state_save_interval must be greater than 0"));
+    }
+    let signer_policy = SignerPolicy::from_config(config["signer_policy"].as_str())?;
+    let duplicate_policy = DuplicatePolicy::from_config(config["duplicate_policy"].as_str())?;
+    let poll_min_interval = config["poll_min_interval_ms"].as_u64().unwrap_or(100);
+    if poll_min_interval == 0 {
+        return Err(anyhow!("poll_min_interval_ms must be greater than 0"));
+    }
+    let polling = PollingConfig::new(
+        poll_min_interval,
+        config["poll_max_interval_ms"].as_u64().unwrap_or(10000),
+        config["poll_backoff_factor"].as_f64().unwrap_or(2.0)
+    );
+    let journal_file = config["journal_file"].as_str();
+    let retention = RetentionPolicy::new(
+        config["retention_max_age_secs"].as_u64(),
+        config["retention_max_readings_per_sensor"].as_u64(),
+        config["retention_max_bytes"].as_u64()
+    );
+    let compaction_interval = config["compaction_interval_secs"].as_u64().unwrap_or(60);
+    if compaction_interval == 0 {
+        return Err(anyhow!("compaction_interval_secs must be greater than 0"));
+    }
+    let dead_letter_max_count = config["dead_letter_max_count"].as_u64().unwrap_or(10000) as usize;
+    let confidence_policies = Arc::new(ConfidencePolicies::new(
+        config["confidence_policy"].as_str().unwrap_or("sum"),
+        config["confidence_weights"].as_object()
+            .map(|weights| weights.iter()
+                .filter_map(|(ann, weight)| weight.as_f64().map(|weight| (ann.clone(), weight)))
+                .collect())
+            .unwrap_or_default(),
+        config["confidence_required"].as_array()
+            .map(|required| required.iter()
+                .filter_map(|ann| ann.as_str().map(String::from))
+                .collect())
+            .unwrap_or_default()
+    )?);
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() > 1 && args[1] == "replay" {
+        return replay(&args, signer_policy, duplicate_policy, retention, dead_letter_max_count);
+    }
 
-    let annotation_store = Arc::new(Mutex::new(AnnotationStore::new()));
-    let reading_store = Arc::new(Mutex::new(ReadingStore::new()));
+    let annotation_store: Arc<Mutex<dyn AnnotationStorage>>;
+    let reading_store: Arc<Mutex<dyn ReadingStorage>>;
+    let dead_letter_store: Arc<Mutex<DeadLetterStore>>;
+    match config["storage_backend"].as_str().unwrap_or("memory") {
+        "sled" => {
+            let path = config["storage_path"].as_str().unwrap_or("author_console.db");
+            println!("Opening sled store at {}...", path);
+            let db = open_sled(path).unwrap();
+            annotation_store = Arc::new(Mutex::new(SledAnnotationStore::open(&db).unwrap()));
+            reading_store = Arc::new(Mutex::new(SledReadingStore::open(&db).unwrap()));
+            dead_letter_store = Arc::new(Mutex::new(DeadLetterStore::open(&db).unwrap().with_max_len(dead_letter_max_count)));
+        },
+        "memory" => {
+            annotation_store = Arc::new(Mutex::new(AnnotationStore::new()));
+            reading_store = Arc::new(Mutex::new(ReadingStore::new()));
+            dead_letter_store = Arc::new(Mutex::new(DeadLetterStore::new().with_max_len(dead_letter_max_count)));
+        },
+        backend => return Err(anyhow!("Unknown storage_backend: {}", backend))
+    }
+    let ingest_stats = Arc::new(Mutex::new(IngestStats::new()));
+    let ingestor = Arc::new(MessageIngestor::new(
+        signer_policy,
+        duplicate_policy,
+        annotation_store.clone(),
+        reading_store.clone(),
+        dead_letter_store.clone(),
+        ingest_stats.clone(),
+        journal_file.map(|path| Journal::open(path).unwrap())
+    ));
+
+    let compactor = Compactor::new(retention, reading_store.clone(), annotation_store.clone(), dead_letter_store.clone());
+
+    // Rebuild the stores from the journal, unless they were already persisted by the backend
+    if let Some(path) = journal_file {
+        let empty = annotation_store.lock().unwrap().is_empty()
+            && reading_store.lock().unwrap().is_empty()
+            && dead_letter_store.lock().unwrap().is_empty();
+        if empty && Path::new(path).exists() {
+            println!("Replaying journal {}...", path);
+            let (replayed, failed) = ingestor.replay(path, &retention)?;
+            println!("Replayed {} journal entries, {} could not be stored", replayed, failed);
+            if !retention.is_unbounded() {
+                let (readings, annotations, dead_letters) = compactor.compact()?;
+                println!("Evicted {} readings, {} annotations and {} dead letters", readings, annotations, dead_letters);
+            }
+        }
+    }
 
-    println!("Making Streams channel...");
-    println!("node = {}", config["node"]);
-    println!("seed = {}", seed.as_str());
-    let author = Arc::new(Mutex::new(ChannelAuthor::new(seed.as_str(), mwm, local_pow, node).unwrap()));
-    let channel_address = author.lock().unwrap().get_announcement_id().unwrap();
+    let mode = config["mode"].as_str().unwrap_or("new");
+    if mode != "new" && mode != "recover" {
+        return Err(anyhow!("Unknown mode: {}", mode));
+    }
+
+    let channel_author = match &state_file {
+        Some(state_file) if state_file.exists() => {
+            println!("Restoring Streams channel from {}...", state_file.get_path());
+            println!("node = {}", config["node"]);
+            state_file.load(node).unwrap()
+        },
+        _ if mode == "recover" => {
+            let announcement_id = config["announcement_id"].as_str()
+                .ok_or_else(|| anyhow!("announcement_id must be set in recover mode"))?;
+            if config["seed"].is_null() {
+                return Err(anyhow!("seed must be set in recover mode"));
+            }
+            println!("Recovering Streams channel {}...", announcement_id);
+            println!("node = {}", config["node"]);
+            println!("seed = {}", seed.as_str());
+            ChannelAuthor::recover(seed.as_str(), announcement_id, node).unwrap()
+        },
+        _ => {
+            println!("Making Streams channel...");
+            println!("node = {}", config["node"]);
+            println!("seed = {}", seed.as_str());
+            ChannelAuthor::new(seed.as_str(), mwm, local_pow, node).unwrap()
+        }
+    };
+    if let Some(state_file) = &state_file {
+        state_file.save(&channel_author)?;
+    }
+
+    let author = AuthorHandle::spawn(channel_author)?;
+    let channel_address = author.get_announcement_id()?;
     println!("\nChannel Address - {}:{}\n", channel_address.0, channel_address.1);
 
     let retriever = MessageRetriever::new(
             author.clone(),
-            annotation_store.clone(),
-            reading_store.clone()
+            ingestor.clone(),
+            node,
+            polling
         );
+    let retriever_status = retriever.get_status();
     MessageRetriever::start(retriever).unwrap();
 
-    match api_server::start(port, author, annotation_store, reading_store).await {
-        Ok(_) => Ok(()),
-        Err(e) => Err(anyhow::anyhow!(e))
+    if !retention.is_unbounded() {
+        Compactor::start(
+            compactor,
+            Duration::from_secs(compaction_interval)
+        ).unwrap();
+    }
+
+    if let Some(state_file) = &state_file {
+        AuthorStateFile::start_autosave(
+            state_file.clone(),
+            author.clone(),
+            Duration::from_secs(state_save_interval)
+        ).unwrap();
+    }
+
+    let result = tokio::select! {
+        res = api_server::start(
+            port,
+            author.clone(),
+            annotation_store,
+            reading_store,
+            dead_letter_store,
+            ingest_stats,
+            ingestor,
+            retriever_status,
+            confidence_policies
+        ) => {
+            res.map_err(|e| anyhow::anyhow!(e))
+        }
+        res = shutdown_signal() => {
+            println!("Shutting down...");
+            res
+        }
+    };
+
+    if let Some(state_file) = &state_file {
+        println!("Saving author state to {}", state_file.get_path());
+        state_file.save_from(&author).await?;
+    }
+
+    result
+}
+
+/// Resolves on `Ctrl+C`, or on SIGTERM as sent by process managers and container runtimes
+#[cfg(unix)]
+async fn shutdown_signal() -> Result<()> {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res?,
+        _ = terminate.recv() => ()
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> Result<()> {
+    Ok(tokio::signal::ctrl_c().await?)
+}
+
+/// `author-console replay <journal_file> <store_path>` rebuilds a fresh sled store from a journal
+fn replay(
+    args: &[String],
+    signer_policy: SignerPolicy,
+    duplicate_policy: DuplicatePolicy,
+    retention: RetentionPolicy,
+    dead_letter_max_count: usize
+) -> Result<()> {
+    if args.len() != 4 {
+        return Err(anyhow!("Usage: author-console replay <journal_file> <store_path>"));
+    }
+    let journal_file = &args[2];
+    let store_path = &args[3];
+
+    let db = open_sled(store_path)?;
+    let annotation_store: Arc<Mutex<dyn AnnotationStorage>> = Arc::new(Mutex::new(SledAnnotationStore::open(&db)?));
+    let reading_store: Arc<Mutex<dyn ReadingStorage>> = Arc::new(Mutex::new(SledReadingStore::open(&db)?));
+    let dead_letter_store = Arc::new(Mutex::new(DeadLetterStore::open(&db)?.with_max_len(dead_letter_max_count)));
+    if !annotation_store.lock().unwrap().is_empty()
+        || !reading_store.lock().unwrap().is_empty()
+        || !dead_letter_store.lock().unwrap().is_empty() {
+        return Err(anyhow!("Store at {} is not empty", store_path));
+    }
+
+    let ingestor = MessageIngestor::new(
+        signer_policy,
+        duplicate_policy,
+        annotation_store.clone(),
+        reading_store.clone(),
+        dead_letter_store.clone(),
+        Arc::new(Mutex::new(IngestStats::new())),
+        None
+    );
+
+    println!("Replaying journal {} into {}...", journal_file, store_path);
+    let (replayed, failed) = ingestor.replay(journal_file, &retention)?;
+    if !retention.is_unbounded() {
+        Compactor::new(retention, reading_store, annotation_store, dead_letter_store.clone()).compact()?;
     }
+    db.flush()?;
+    println!(
+        "Replayed {} journal entries, {} could not be decoded, {} could not be stored",
+        replayed,
+        dead_letter_store.lock().unwrap().ids().len(),
+        failed
+    );
+    Ok(())
 }
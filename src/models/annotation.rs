@@ -1,10 +1,12 @@
-use crate::models::{ReadingId, AlvariumAnnotation};
+use crate::models::{ReadingId, AlvariumAnnotation, MessageMetadata};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Annotation {
     pub reading_id: ReadingId,
     pub annotation: AlvariumAnnotation,
+    #[serde(default)]
+    pub metadata: MessageMetadata,
 }
 
 impl Annotation {
@@ -12,6 +14,7 @@ impl Annotation {
         Annotation {
             reading_id: ReadingId::default(),
             annotation: AlvariumAnnotation::default(),
+            metadata: MessageMetadata::default(),
         }
     }
 
@@ -25,6 +28,11 @@ impl Annotation {
         self
     }
 
+    pub fn with_metadata(mut self, metadata: MessageMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
     pub fn get_reading_id(&self) -> &ReadingId {
         &self.reading_id
     }
@@ -33,6 +41,10 @@ impl Annotation {
         &self.annotation
     }
 
+    pub fn get_metadata(&self) -> &MessageMetadata {
+        &self.metadata
+    }
+
     pub fn get_confidence_score(&self) -> f64 {
         self.annotation.payload.avl
     }
@@ -0,0 +1,210 @@
+use anyhow::{Result, anyhow};
+use crate::models::Annotation;
+use std::collections::{BTreeSet, HashMap};
+
+/// Turns the annotations of a reading into a confidence score
+pub trait ConfidencePolicy: Send + Sync {
+    fn score(&self, annotations: &[&Annotation]) -> f64;
+}
+
+/// Sum of `avl` over every annotation, unbounded
+pub struct SumPolicy;
+
+impl ConfidencePolicy for SumPolicy {
+    fn score(&self, annotations: &[&Annotation]) -> f64 {
+        annotations.iter().map(|ann| ann.get_confidence_score()).sum()
+    }
+}
+
+/// Sum of `avl` weighted by the `ann` type of each annotation. Types without a weight count once.
+pub struct WeightedPolicy {
+    weights: HashMap<String, f64>,
+}
+
+impl WeightedPolicy {
+    pub fn new(weights: HashMap<String, f64>) -> Self {
+        WeightedPolicy { weights }
+    }
+
+    fn weight(&self, ann: &str) -> f64 {
+        self.weights.get(ann).cloned().unwrap_or(1.0)
+    }
+}
+
+impl ConfidencePolicy for WeightedPolicy {
+    fn score(&self, annotations: &[&Annotation]) -> f64 {
+        annotations.iter()
+            .map(|ann| self.weight(&ann.annotation.payload.ann) * ann.get_confidence_score())
+            .sum()
+    }
+}
+
+/// Score between 0 and 1. Each annotation type counts once, with the best `avl` (clamped to 0..1)
+/// seen for it, and the types are averaged by their weight. If weights are configured, every
+/// weighted type is expected and a missing one counts as 0, otherwise the types present are
+/// averaged equally.
+pub struct NormalizedPolicy {
+    weights: HashMap<String, f64>,
+}
+
+impl NormalizedPolicy {
+    pub fn new(weights: HashMap<String, f64>) -> Self {
+        NormalizedPolicy { weights }
+    }
+}
+
+impl ConfidencePolicy for NormalizedPolicy {
+    fn score(&self, annotations: &[&Annotation]) -> f64 {
+        let mut best = HashMap::<&str, f64>::new();
+        for ann in annotations {
+            let avl = ann.get_confidence_score().max(0.0).min(1.0);
+            let entry = best.entry(ann.annotation.payload.ann.as_str()).or_insert(0.0);
+            *entry = entry.max(avl);
+        }
+
+        let (total, weight) = if self.weights.is_empty() {
+            (best.values().sum(), best.len() as f64)
+        } else {
+            self.weights.iter().fold((0.0, 0.0), |(total, weight), (ann, w)| {
+                (total + w * best.get(ann.as_str()).cloned().unwrap_or(0.0), weight + w)
+            })
+        };
+
+        if weight > 0.0 { total / weight } else { 0.0 }
+    }
+}
+
+/// 1 if every required annotation type is present with a positive `avl`, 0 otherwise
+pub struct RequiredSetPolicy {
+    required: BTreeSet<String>,
+}
+
+impl RequiredSetPolicy {
+    pub fn new<I: IntoIterator<Item = String>>(required: I) -> Self {
+        RequiredSetPolicy { required: required.into_iter().collect() }
+    }
+}
+
+impl ConfidencePolicy for RequiredSetPolicy {
+    fn score(&self, annotations: &[&Annotation]) -> f64 {
+        let present: BTreeSet<&str> = annotations.iter()
+            .filter(|ann| ann.get_confidence_score() > 0.0)
+            .map(|ann| ann.annotation.payload.ann.as_str())
+            .collect();
+
+        if self.required.iter().all(|ann| present.contains(ann.as_str())) { 1.0 } else { 0.0 }
+    }
+}
+
+/// Registry of the confidence policies that can be selected by name, along with the default one
+pub struct ConfidencePolicies {
+    default: String,
+    policies: HashMap<String, Box<dyn ConfidencePolicy>>,
+}
+
+impl ConfidencePolicies {
+    /// Registers the built in `sum`, `weighted`, `normalized` and, if `required` is not empty,
+    /// `required` policies
+    pub fn new(default: &str, weights: HashMap<String, f64>, required: Vec<String>) -> Result<Self> {
+        let mut policies = ConfidencePolicies {
+            default: default.to_string(),
+            policies: HashMap::new()
+        };
+
+        policies.register("sum", Box::new(SumPolicy));
+        policies.register("weighted", Box::new(WeightedPolicy::new(weights.clone())));
+        policies.register("normalized", Box::new(NormalizedPolicy::new(weights)));
+
+        // With nothing required every reading would score 1, so the policy is only offered when
+        // the required types are configured
+        if !required.is_empty() {
+            policies.register("required", Box::new(RequiredSetPolicy::new(required)));
+        } else if default == "required" {
+            return Err(anyhow!("confidence_required must not be empty when confidence_policy is \"required\""));
+        }
+
+        policies.get(None)?;
+        Ok(policies)
+    }
+
+    pub fn register(&mut self, name: &str, policy: Box<dyn ConfidencePolicy>) {
+        self.policies.insert(name.to_string(), policy);
+    }
+
+    /// Returns the named policy, or the default one if no name is given
+    pub fn get(&self, name: Option<&str>) -> Result<&dyn ConfidencePolicy> {
+        let name = name.unwrap_or(&self.default);
+        match self.policies.get(name) {
+            Some(policy) => Ok(policy.as_ref()),
+            None => Err(anyhow!("Unknown confidence policy: {}", name))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AlvariumAnnotation;
+
+    fn annotation(ann: &str, avl: f64) -> Annotation {
+        let mut annotation = AlvariumAnnotation::default();
+        annotation.payload.ann = ann.to_string();
+        annotation.payload.avl = avl;
+        Annotation::new().with_annotation(annotation)
+    }
+
+    fn weights(weights: &[(&str, f64)]) -> HashMap<String, f64> {
+        weights.iter().map(|(ann, weight)| (ann.to_string(), *weight)).collect()
+    }
+
+    #[test]
+    fn normalized_uses_the_best_avl_per_weighted_type() {
+        let annotations = [annotation("pki", 0.5), annotation("pki", 1.0), annotation("tpm", 0.5), annotation("tls", 1.0)];
+        let annotations: Vec<&Annotation> = annotations.iter().collect();
+
+        // pki counts 1.0 with weight 2, tpm 0.5 with weight 1 and the unweighted tls is ignored
+        let policy = NormalizedPolicy::new(weights(&[("pki", 2.0), ("tpm", 1.0)]));
+        assert!((policy.score(&annotations) - 2.5 / 3.0).abs() < 1e-9);
+
+        // A weighted type without annotations counts as 0
+        let policy = NormalizedPolicy::new(weights(&[("pki", 1.0), ("source", 1.0)]));
+        assert!((policy.score(&annotations) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn normalized_without_weights_averages_the_types_present() {
+        let annotations = [annotation("pki", 3.0), annotation("tpm", -1.0), annotation("tls", 0.5)];
+        let annotations: Vec<&Annotation> = annotations.iter().collect();
+
+        // avl is clamped to 0..1
+        let policy = NormalizedPolicy::new(HashMap::new());
+        assert!((policy.score(&annotations) - 1.5 / 3.0).abs() < 1e-9);
+        assert_eq!(policy.score(&[]), 0.0);
+    }
+
+    #[test]
+    fn required_needs_every_type_with_a_positive_avl() {
+        let policy = RequiredSetPolicy::new(vec!["pki".to_string(), "tpm".to_string()]);
+
+        let complete = [annotation("pki", 1.0), annotation("tpm", 0.2), annotation("tls", 0.0)];
+        assert_eq!(policy.score(&complete.iter().collect::<Vec<_>>()), 1.0);
+
+        let failed = [annotation("pki", 1.0), annotation("tpm", 0.0)];
+        assert_eq!(policy.score(&failed.iter().collect::<Vec<_>>()), 0.0);
+
+        let missing = [annotation("pki", 1.0)];
+        assert_eq!(policy.score(&missing.iter().collect::<Vec<_>>()), 0.0);
+    }
+
+    #[test]
+    fn required_set_must_not_be_empty() {
+        assert!(ConfidencePolicies::new("required", HashMap::new(), vec![]).is_err());
+
+        let policies = ConfidencePolicies::new("sum", HashMap::new(), vec![]).unwrap();
+        assert!(policies.get(Some("required")).is_err());
+        assert!(policies.get(Some("normalized")).is_ok());
+
+        let policies = ConfidencePolicies::new("required", HashMap::new(), vec!["pki".to_string()]).unwrap();
+        assert!(policies.get(None).is_ok());
+    }
+}
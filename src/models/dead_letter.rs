@@ -0,0 +1,32 @@
+use anyhow::Result;
+use crate::models::{MessageMetadata, RawMessage};
+use serde::{Deserialize, Serialize};
+
+/// A packet whose masked payload could not be decoded, kept so it can be inspected and
+/// re-processed once the decoder is fixed
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeadLetter {
+    pub id: u64,
+    pub metadata: MessageMetadata,
+    /// Base64 encoded raw masked payload
+    pub masked_payload: String,
+    pub error: String,
+}
+
+impl DeadLetter {
+    pub fn new(id: u64, msg: &RawMessage, error: String) -> Self {
+        DeadLetter {
+            id,
+            metadata: msg.metadata.clone(),
+            masked_payload: base64::encode(&msg.masked_payload),
+            error,
+        }
+    }
+
+    pub fn to_raw_message(&self) -> Result<RawMessage> {
+        Ok(RawMessage {
+            metadata: self.metadata.clone(),
+            masked_payload: base64::decode(&self.masked_payload)?,
+        })
+    }
+}
\ No newline at end of file
@@ -0,0 +1,36 @@
+use crate::models::{Reading, Annotation, MessageMetadata};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Versioned wrapper for payloads published into the channel. The `type` decides which handler
+/// the `body` is decoded with.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MessageEnvelope {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub version: u32,
+    pub body: Value,
+}
+
+/// A packet received from the channel, before its masked payload is decoded
+#[derive(Clone, Debug)]
+pub struct RawMessage {
+    pub metadata: MessageMetadata,
+    pub masked_payload: Vec<u8>,
+}
+
+/// A record decoded from the masked payload of a packet
+#[derive(Debug)]
+pub enum ChannelPayload {
+    Reading(Reading),
+    Annotation(Annotation),
+}
+
+impl ChannelPayload {
+    pub fn with_metadata(self, metadata: MessageMetadata) -> Self {
+        match self {
+            ChannelPayload::Reading(reading) => ChannelPayload::Reading(reading.with_metadata(metadata)),
+            ChannelPayload::Annotation(annotation) => ChannelPayload::Annotation(annotation.with_metadata(metadata)),
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Information about the Streams message a record was received in. This is filled in by the
+/// console on ingestion, values provided by the publisher in the payload are overwritten.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MessageMetadata {
+    pub link: String,
+    pub received_at: u64,
+    pub signed: bool,
+    pub publisher: Option<String>,
+    pub signer_mismatch: bool,
+    /// Set on readings that were not signed, so their `sensor_id` could not be checked
+    #[serde(default)]
+    pub unverified: bool,
+    pub public_payload: Option<Value>,
+}
+
+impl MessageMetadata {
+    /// `link` is the `appinst:msgid` address of the message and `publisher` the hex encoded
+    /// public key of the signer, tagged packets have none
+    pub fn new(link: String, publisher: Option<String>, public_payload: &[u8]) -> Self {
+        MessageMetadata {
+            link,
+            received_at: now_millis(),
+            signed: publisher.is_some(),
+            publisher,
+            signer_mismatch: false,
+            unverified: false,
+            public_payload: Self::parse_public_payload(public_payload),
+        }
+    }
+
+    /// Public payloads are expected to be JSON, anything else is kept as a plain string (or hex
+    /// if it is not valid utf-8) so that it is not lost
+    pub fn parse_public_payload(bytes: &[u8]) -> Option<Value> {
+        if bytes.is_empty() {
+            return None
+        }
+
+        match serde_json::from_slice(bytes) {
+            Ok(value) => Some(value),
+            Err(_) => match std::str::from_utf8(bytes) {
+                Ok(s) => Some(Value::String(s.to_string())),
+                Err(_) => Some(Value::String(hex::encode(bytes)))
+            }
+        }
+    }
+
+    /// An object filter matches if every one of its fields is present with the same value in the
+    /// public payload, any other filter value has to equal the public payload
+    pub fn matches_public_payload(&self, filter: &Value) -> bool {
+        match (&self.public_payload, filter) {
+            (Some(Value::Object(payload)), Value::Object(fields)) => {
+                fields.iter().all(|(key, value)| payload.get(key) == Some(value))
+            },
+            (Some(payload), filter) => payload == filter,
+            (None, _) => false
+        }
+    }
+}
+
+/// Milliseconds since the unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
\ No newline at end of file
@@ -2,11 +2,23 @@ pub mod reading;
 pub mod annotation;
 pub mod subscription;
 pub mod alvarium;
+pub mod psk;
+pub mod packet;
+pub mod metadata;
+pub mod envelope;
+pub mod dead_letter;
+pub mod confidence;
 
 pub use reading::*;
 pub use annotation::*;
 pub use subscription::*;
 pub use alvarium::*;
+pub use psk::*;
+pub use packet::*;
+pub use metadata::*;
+pub use envelope::*;
+pub use dead_letter::*;
+pub use confidence::*;
 
 use serde::{Deserialize, Serialize};
 
@@ -0,0 +1,8 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PacketRequest {
+    pub link_to: Option<String>,
+    pub public_payload: String,
+    pub masked_payload: String,
+}
\ No newline at end of file
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PskGroupRequest {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PskKeyloadRequest {
+    pub names: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PskGroup {
+    pub name: String,
+    pub pskid: String,
+    pub psk: Option<String>,
+    pub keyload_link: Option<String>,
+}
\ No newline at end of file
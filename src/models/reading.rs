@@ -1,4 +1,4 @@
-use crate::models::{SensorId, ReadingId, Bytes};
+use crate::models::{SensorId, ReadingId, Bytes, MessageMetadata};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -6,6 +6,11 @@ pub struct Reading {
     pub sensor_id: SensorId,
     pub reading_id: ReadingId,
     pub data: Bytes,
+    /// Time the sensor took the reading at, in milliseconds since the unix epoch, if it sent one
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    #[serde(default)]
+    pub metadata: MessageMetadata,
 }
 
 impl Reading {
@@ -14,6 +19,8 @@ impl Reading {
             sensor_id: SensorId::default(),
             reading_id: ReadingId::default(),
             data: Bytes::default(),
+            timestamp: None,
+            metadata: MessageMetadata::default(),
         }
     }
 
@@ -32,6 +39,11 @@ impl Reading {
         self
     }
 
+    pub fn with_metadata(mut self, metadata: MessageMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
     pub fn get_sensor_id(&self) -> &SensorId {
         &self.sensor_id
     }
@@ -43,4 +55,8 @@ impl Reading {
     pub fn get_data(&self) -> &Bytes {
         &self.data
     }
+
+    pub fn get_metadata(&self) -> &MessageMetadata {
+        &self.metadata
+    }
 }
\ No newline at end of file
@@ -4,4 +4,43 @@ use serde::{Deserialize, Serialize};
 pub struct SubscriptionRequest {
     pub msgid: String,
     pub pk: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RevokeRequest {
+    pub pk: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SubscriptionResult {
+    pub msgid: String,
+    pub pk: String,
+    pub subscribed: bool,
+    pub error: Option<String>,
+}
+
+impl SubscriptionResult {
+    pub fn subscribed(request: &SubscriptionRequest) -> Self {
+        SubscriptionResult {
+            msgid: request.msgid.clone(),
+            pk: request.pk.clone(),
+            subscribed: true,
+            error: None,
+        }
+    }
+
+    pub fn failed(request: &SubscriptionRequest, error: String) -> Self {
+        SubscriptionResult {
+            msgid: request.msgid.clone(),
+            pk: request.pk.clone(),
+            subscribed: false,
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchSubscriptionResponse {
+    pub keyload_link: Option<String>,
+    pub results: Vec<SubscriptionResult>,
 }
\ No newline at end of file
@@ -1,53 +1,450 @@
 use anyhow::{Result, anyhow};
 use crate::models::{ReadingId, Annotation};
-use std::collections::{
-    hash_map::Iter,
-    HashMap
+use crate::store::{record_size, select_expired, time_range, unindex, AnnotationStorage, Cursor, Records, RetentionPolicy, TimeIndex};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap},
+    ops::Bound
 };
 
 use serde::{Deserialize, Serialize};
 
+/// Annotations are kept once under a store assigned id, in insertion order. Every field that can
+/// be filtered on has an index from its value to the ids holding it, so queries never need to
+/// scan the whole store. The annotations of a reading are ordered by the time they were received at.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AnnotationStore {
-    annotations: HashMap<ReadingId, Vec<Annotation>>
+    next_id: u64,
+    annotations: BTreeMap<u64, Annotation>,
+    by_reading: HashMap<ReadingId, TimeIndex>,
+    by_iss: HashMap<String, BTreeSet<u64>>,
+    by_sub: HashMap<String, BTreeSet<u64>>,
+    by_jti: HashMap<String, BTreeSet<u64>>,
+    by_ann: HashMap<String, BTreeSet<u64>>,
+    by_iat: BTreeMap<u64, BTreeSet<u64>>,
+    by_time: TimeIndex,
+    bytes: usize,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Query over the stored annotations. All the predicates that are set must hold, together with
+/// every filter in `all` and, if it is not empty, at least one filter in `any`. A filter with
+/// nothing set matches every annotation.
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct AnnotationStoreFilter {
     pub iss: Option<String>,
     pub sub: Option<String>,
     pub iat: Option<u64>,
     pub jti: Option<String>,
     pub ann: Option<String>,
+    pub iat_from: Option<u64>,
+    pub iat_to: Option<u64>,
+    pub avl_min: Option<f64>,
+    pub avl_max: Option<f64>,
+    pub reading_ids: Option<Vec<ReadingId>>,
+    #[serde(default)]
+    pub all: Vec<AnnotationStoreFilter>,
+    #[serde(default)]
+    pub any: Vec<AnnotationStoreFilter>,
+}
+
+impl AnnotationStoreFilter {
+    pub fn matches(&self, annotation: &Annotation) -> bool {
+        let payload = &annotation.annotation.payload;
+        self.iss.as_ref().map_or(true, |iss| iss == &payload.iss)
+            && self.sub.as_ref().map_or(true, |sub| sub == &payload.sub)
+            && self.jti.as_ref().map_or(true, |jti| jti == &payload.jti)
+            && self.ann.as_ref().map_or(true, |ann| ann == &payload.ann)
+            && self.iat.map_or(true, |iat| iat == payload.iat)
+            && self.iat_from.map_or(true, |from| payload.iat >= from)
+            && self.iat_to.map_or(true, |to| payload.iat <= to)
+            && self.avl_min.map_or(true, |min| payload.avl >= min)
+            && self.avl_max.map_or(true, |max| payload.avl <= max)
+            && self.reading_ids.as_ref().map_or(true, |ids| ids.contains(annotation.get_reading_id()))
+            && self.all.iter().all(|filter| filter.matches(annotation))
+            && (self.any.is_empty() || self.any.iter().any(|filter| filter.matches(annotation)))
+    }
 }
 
 impl AnnotationStore {
     pub fn new() -> Self {
         AnnotationStore {
-            annotations: HashMap::<ReadingId, Vec<Annotation>>::new()
+            next_id: 0,
+            annotations: BTreeMap::new(),
+            by_reading: HashMap::new(),
+            by_iss: HashMap::new(),
+            by_sub: HashMap::new(),
+            by_jti: HashMap::new(),
+            by_ann: HashMap::new(),
+            by_iat: BTreeMap::new(),
+            by_time: TimeIndex::new(),
+            bytes: 0,
         }
     }
 
-    pub fn insert(&mut self, reading_id: &ReadingId, annotation: Annotation) -> Result<()> {
-        match self.annotations.get_mut(reading_id) {
-            Some(annotations) => Ok(annotations.push(annotation)),
-            None => {
-                self.annotations.insert(reading_id.clone(), vec![annotation]);
-                Ok(())
-            },
+    /// Stores the annotation under a given id, used when the ids are assigned by a persistent backend
+    pub fn insert_with_id(&mut self, id: u64, annotation: Annotation) {
+        self.next_id = std::cmp::max(self.next_id, id + 1);
+
+        let payload = &annotation.annotation.payload;
+        let cursor = Cursor::new(annotation.get_metadata().received_at, id);
+        self.by_reading.entry(annotation.get_reading_id().clone()).or_default().insert(cursor);
+        index(&mut self.by_iss, payload.iss.clone(), id);
+        index(&mut self.by_sub, payload.sub.clone(), id);
+        index(&mut self.by_jti, payload.jti.clone(), id);
+        index(&mut self.by_ann, payload.ann.clone(), id);
+        self.by_iat.entry(payload.iat).or_default().insert(id);
+        self.by_time.insert(cursor);
+
+        self.bytes += record_size(&annotation);
+        self.annotations.insert(id, annotation);
+    }
+
+    /// Ids of the annotations with the given `jti` published by `publisher`
+    pub fn find(&self, jti: &str, publisher: Option<&str>) -> Vec<u64> {
+        self.by_jti.get(jti).map_or(Vec::new(), |ids| ids.iter()
+            .filter(|id| self.annotations.get(id)
+                .map_or(false, |annotation| annotation.get_metadata().publisher.as_deref() == publisher))
+            .cloned()
+            .collect())
+    }
+
+    pub fn remove_id(&mut self, id: u64) -> Option<Annotation> {
+        let annotation = self.annotations.remove(&id)?;
+        let payload = &annotation.annotation.payload;
+        let cursor = Cursor::new(annotation.get_metadata().received_at, id);
+        unindex(&mut self.by_reading, annotation.get_reading_id(), &cursor);
+        unindex(&mut self.by_iss, &payload.iss, &id);
+        unindex(&mut self.by_sub, &payload.sub, &id);
+        unindex(&mut self.by_jti, &payload.jti, &id);
+        unindex(&mut self.by_ann, &payload.ann, &id);
+        if let Some(ids) = self.by_iat.get_mut(&payload.iat) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.by_iat.remove(&payload.iat);
+            }
+        }
+        self.by_time.remove(&cursor);
+        self.bytes = self.bytes.saturating_sub(record_size(&annotation));
+        Some(annotation)
+    }
+
+    /// Ids of the annotations of a reading
+    pub fn find_for_reading(&self, reading_id: &ReadingId) -> Vec<u64> {
+        self.by_reading.get(reading_id).map_or(Vec::new(), |ids| ids.iter().map(|cursor| cursor.id).collect())
+    }
+
+    /// Ids of the annotations outside the age and size limits of the retention policy
+    pub fn expired(&self, retention: &RetentionPolicy, now: u64) -> BTreeSet<u64> {
+        let mut evicted = BTreeSet::new();
+        select_expired(&self.by_time, self.bytes, retention, now, &mut evicted, |id| {
+            self.annotations.get(&id).map_or(0, record_size)
+        });
+        evicted
+    }
+
+    /// Returns every annotation matching the filter once, in insertion order, starting after the
+    /// store id of `after`. Annotations are matched as the records are taken, so a page only
+    /// checks the candidates up to its last record.
+    pub fn filter<'a>(&'a self, filter: &'a AnnotationStoreFilter, after: Option<Cursor>) -> Records<'a, Annotation> {
+        let annotations = &self.annotations;
+        Box::new(self.select(filter, after.map(|after| after.id)).filter_map(move |id| annotations.get(&id)
+            .map(|annotation| (Cursor::new(annotation.get_metadata().received_at, id), annotation))))
+    }
+
+    fn select<'a>(&'a self, filter: &'a AnnotationStoreFilter, after: Option<u64>) -> Ids<'a> {
+        let annotations = &self.annotations;
+        Box::new(self.candidates(filter, after)
+            .filter(move |id| annotations.get(id).map_or(false, |annotation| filter.matches(annotation))))
+    }
+
+    /// Narrows the ids after `after` that can match the filter down using the indexes, in
+    /// ascending order. The candidates still have to be checked against the whole filter.
+    fn candidates<'a>(&'a self, filter: &'a AnnotationStoreFilter, after: Option<u64>) -> Ids<'a> {
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        let mut indexed = Vec::new();
+        let exact = [
+            (&filter.iss, &self.by_iss),
+            (&filter.sub, &self.by_sub),
+            (&filter.jti, &self.by_jti),
+            (&filter.ann, &self.by_ann),
+        ];
+        for (value, index) in exact.iter() {
+            if let Some(value) = value {
+                match index.get(value) {
+                    Some(ids) => indexed.push(ids),
+                    None => return Box::new(std::iter::empty())
+                }
+            }
+        }
+        if let Some(iat) = filter.iat {
+            match self.by_iat.get(&iat) {
+                Some(ids) => indexed.push(ids),
+                None => return Box::new(std::iter::empty())
+            }
+        }
+        if let Some(ids) = indexed.into_iter().min_by_key(|ids| ids.len()) {
+            return Box::new(ids.range((start, Bound::Unbounded)).cloned())
+        }
+
+        if filter.iat_from.is_some() || filter.iat_to.is_some() {
+            let from = filter.iat_from.unwrap_or(0);
+            let to = filter.iat_to.unwrap_or(u64::MAX);
+            if from > to {
+                return Box::new(std::iter::empty())
+            }
+            return union(self.by_iat.range(from..=to)
+                .map(|(_, ids)| Box::new(ids.range((start, Bound::Unbounded)).cloned()) as Ids<'a>)
+                .collect())
+        }
+        if let Some(reading_ids) = &filter.reading_ids {
+            // The annotations of a reading are indexed by receive time, which does not follow the
+            // store ids, so these are sorted here
+            let ids: BTreeSet<u64> = reading_ids.iter()
+                .filter_map(|reading_id| self.by_reading.get(reading_id))
+                .flat_map(|ids| ids.iter().map(|cursor| cursor.id))
+                .filter(|id| after.map_or(true, |after| *id > after))
+                .collect();
+            return Box::new(ids.into_iter())
+        }
+        if let Some(all) = filter.all.first() {
+            return self.select(all, after)
+        }
+        if !filter.any.is_empty() {
+            return union(filter.any.iter().map(|any| self.select(any, after)).collect())
+        }
+        Box::new(self.annotations.range((start, Bound::Unbounded)).map(|(id, _)| *id))
+    }
+}
+
+type Ids<'a> = Box<dyn Iterator<Item = u64> + 'a>;
+
+/// Merges ascending id iterators into one ascending iterator without duplicates
+fn union(iters: Vec<Ids<'_>>) -> Ids<'_> {
+    let mut union = Union { iters, heap: BinaryHeap::new(), last: None };
+    for i in 0..union.iters.len() {
+        union.advance(i);
+    }
+    Box::new(union)
+}
+
+struct Union<'a> {
+    iters: Vec<Ids<'a>>,
+    heap: BinaryHeap<Reverse<(u64, usize)>>,
+    last: Option<u64>,
+}
+
+impl Union<'_> {
+    fn advance(&mut self, i: usize) {
+        if let Some(id) = self.iters[i].next() {
+            self.heap.push(Reverse((id, i)));
+        }
+    }
+}
+
+impl Iterator for Union<'_> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        while let Some(Reverse((id, i))) = self.heap.pop() {
+            self.advance(i);
+            if self.last != Some(id) {
+                self.last = Some(id);
+                return Some(id)
+            }
         }
+        None
     }
+}
+
+fn index<K: std::hash::Hash + Eq>(index: &mut HashMap<K, BTreeSet<u64>>, key: K, id: u64) {
+    index.entry(key).or_default().insert(id);
+}
 
-    pub fn get(&mut self, reading_id: &ReadingId) -> Result<&Vec<Annotation>> {
-        match self.annotations.get(reading_id) {
-            Some(a) => Ok(a),
+impl AnnotationStorage for AnnotationStore {
+    fn insert(&mut self, reading_id: &ReadingId, annotation: Annotation) -> Result<()> {
+        let id = self.next_id;
+        self.insert_with_id(id, annotation.with_reading_id(reading_id.clone()));
+        Ok(())
+    }
+
+    fn get(&mut self, reading_id: &ReadingId) -> Result<Vec<&Annotation>> {
+        Ok(self.iter_range(reading_id, 0, u64::MAX, None)?.map(|(_, annotation)| annotation).collect())
+    }
+
+    fn iter_range(&mut self, reading_id: &ReadingId, from: u64, to: u64, after: Option<Cursor>) -> Result<Records<'_, Annotation>> {
+        let annotations = &self.annotations;
+        match self.by_reading.get(reading_id) {
+            Some(ids) => Ok(Box::new(time_range(ids, from, to, after)
+                .filter_map(move |cursor| annotations.get(&cursor.id).map(|annotation| (cursor, annotation))))),
             None => {
                 Err(anyhow!("Key not present"))
             }
         }
     }
 
-    pub fn iter(&mut self) -> Result<Iter<ReadingId, Vec<Annotation>>> {
-        Ok(self.annotations.iter())
+    fn filter<'a>(&'a mut self, filter: &'a AnnotationStoreFilter, after: Option<Cursor>) -> Result<Records<'a, Annotation>> {
+        Ok(AnnotationStore::filter(self, filter, after))
+    }
+
+    fn contains(&self, jti: &str, publisher: Option<&str>) -> bool {
+        !self.find(jti, publisher).is_empty()
+    }
+
+    fn remove(&mut self, jti: &str, publisher: Option<&str>) -> Result<Vec<Annotation>> {
+        Ok(self.find(jti, publisher).into_iter().filter_map(|id| self.remove_id(id)).collect())
+    }
+
+    fn compact(&mut self, retention: &RetentionPolicy, now: u64) -> Result<usize> {
+        Ok(self.expired(retention, now).into_iter().filter_map(|id| self.remove_id(id)).count())
+    }
+
+    fn remove_for_reading(&mut self, reading_id: &ReadingId) -> Result<usize> {
+        Ok(self.find_for_reading(reading_id).into_iter().filter_map(|id| self.remove_id(id)).count())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.annotations.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AlvariumAnnotation;
+    use crate::store::Page;
+
+    fn annotation(jti: &str, iss: &str, ann: &str, iat: u64, avl: f64, reading_id: &str) -> Annotation {
+        let mut annotation = AlvariumAnnotation::default();
+        annotation.payload.jti = jti.to_string();
+        annotation.payload.iss = iss.to_string();
+        annotation.payload.ann = ann.to_string();
+        annotation.payload.iat = iat;
+        annotation.payload.avl = avl;
+        Annotation::new()
+            .with_reading_id(ReadingId(reading_id.to_string()))
+            .with_annotation(annotation)
+    }
+
+    fn store() -> AnnotationStore {
+        let mut store = AnnotationStore::new();
+        let annotations = vec![
+            annotation("a", "host-1", "pki", 100, 1.0, "r1"),
+            annotation("b", "host-1", "tpm", 200, 0.5, "r1"),
+            annotation("c", "host-2", "pki", 300, 0.0, "r2"),
+            annotation("d", "host-2", "tls", 400, 1.0, "r3"),
+        ];
+        for annotation in annotations {
+            let reading_id = annotation.get_reading_id().clone();
+            AnnotationStorage::insert(&mut store, &reading_id, annotation).unwrap();
+        }
+        store
+    }
+
+    fn jtis(store: &AnnotationStore, filter: &AnnotationStoreFilter) -> Vec<String> {
+        store.filter(filter, None).map(|(_, annotation)| annotation.annotation.payload.jti.clone()).collect()
+    }
+
+    #[test]
+    fn fields_are_combined_with_and() {
+        let filter = AnnotationStoreFilter {
+            iss: Some("host-1".to_string()),
+            ann: Some("pki".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(jtis(&store(), &filter), vec!["a"]);
+
+        let filter = AnnotationStoreFilter {
+            iss: Some("host-1".to_string()),
+            ann: Some("tls".to_string()),
+            ..Default::default()
+        };
+        assert!(jtis(&store(), &filter).is_empty());
+    }
+
+    #[test]
+    fn any_is_a_union_without_duplicates() {
+        let filter = AnnotationStoreFilter {
+            any: vec![
+                AnnotationStoreFilter { iss: Some("host-1".to_string()), ..Default::default() },
+                AnnotationStoreFilter { ann: Some("pki".to_string()), ..Default::default() },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(jtis(&store(), &filter), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn iat_range() {
+        let filter = AnnotationStoreFilter { iat_from: Some(200), iat_to: Some(300), ..Default::default() };
+        assert_eq!(jtis(&store(), &filter), vec!["b", "c"]);
+
+        let filter = AnnotationStoreFilter { iat_from: Some(300), iat_to: Some(200), ..Default::default() };
+        assert!(jtis(&store(), &filter).is_empty());
+    }
+
+    #[test]
+    fn avl_range() {
+        let filter = AnnotationStoreFilter { avl_min: Some(0.5), ..Default::default() };
+        assert_eq!(jtis(&store(), &filter), vec!["a", "b", "d"]);
+
+        let filter = AnnotationStoreFilter { avl_min: Some(0.1), avl_max: Some(0.5), ..Default::default() };
+        assert_eq!(jtis(&store(), &filter), vec!["b"]);
+    }
+
+    #[test]
+    fn reading_ids() {
+        let filter = AnnotationStoreFilter {
+            reading_ids: Some(vec![ReadingId("r1".to_string()), ReadingId("r3".to_string()), ReadingId("r9".to_string())]),
+            ..Default::default()
+        };
+        assert_eq!(jtis(&store(), &filter), vec!["a", "b", "d"]);
+
+        let filter = AnnotationStoreFilter {
+            reading_ids: Some(vec![ReadingId("r1".to_string())]),
+            ann: Some("tpm".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(jtis(&store(), &filter), vec!["b"]);
+    }
+
+    #[test]
+    fn pages_continue_after_the_cursor() {
+        let mut store = store();
+        AnnotationStorage::insert(&mut store, &ReadingId("r4".to_string()), annotation("e", "host-3", "pki", 150, 1.0, "r4")).unwrap();
+        let filters = vec![
+            AnnotationStoreFilter::default(),
+            AnnotationStoreFilter { iat_from: Some(100), ..Default::default() },
+            AnnotationStoreFilter {
+                any: vec![
+                    AnnotationStoreFilter { ann: Some("pki".to_string()), ..Default::default() },
+                    AnnotationStoreFilter { iss: Some("host-2".to_string()), ..Default::default() },
+                ],
+                ..Default::default()
+            },
+        ];
+        for filter in &filters {
+            let mut pages = Vec::new();
+            let mut after = None;
+            loop {
+                let page = Page::collect(store.filter(filter, after), 2);
+                pages.push(page.items.iter().map(|annotation| annotation.annotation.payload.jti.clone()).collect::<Vec<_>>());
+                match page.next_cursor {
+                    Some(cursor) => after = Some(cursor),
+                    None => break
+                }
+            }
+            assert_eq!(pages.concat(), jtis(&store, filter));
+        }
+        assert_eq!(jtis(&store, &filters[1]), vec!["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn last_cursor_matches_nothing() {
+        let store = store();
+        let filter = AnnotationStoreFilter::default();
+        assert_eq!(store.filter(&filter, Some(Cursor::new(0, u64::MAX))).count(), 0);
+
+        let filter = AnnotationStoreFilter { iss: Some("host-1".to_string()), ..Default::default() };
+        assert_eq!(store.filter(&filter, Some(Cursor::new(0, u64::MAX))).count(), 0);
     }
 }
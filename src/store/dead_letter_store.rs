@@ -0,0 +1,145 @@
+use anyhow::{Result, anyhow};
+use crate::models::{DeadLetter, RawMessage};
+use crate::store::{record_id, Cursor, Records, RetentionPolicy};
+use std::{
+    collections::BTreeMap,
+    ops::Bound
+};
+
+/// Dead letters are kept in memory, and written through to a sled tree when one is set so they
+/// survive restarts. Once `max_len` dead letters are stored the oldest ones are dropped.
+pub struct DeadLetterStore {
+    next_id: u64,
+    dead_letters: BTreeMap<u64, DeadLetter>,
+    tree: Option<sled::Tree>,
+    max_len: Option<usize>,
+}
+
+impl DeadLetterStore {
+    pub fn new() -> Self {
+        DeadLetterStore {
+            next_id: 0,
+            dead_letters: BTreeMap::new(),
+            tree: None,
+            max_len: None,
+        }
+    }
+
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Opens the dead letter tree of a sled database, loading the dead letters already stored in it
+    pub fn open(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree("dead_letters")?;
+
+        let mut store = DeadLetterStore::new();
+        for record in tree.iter() {
+            let (key, value) = record?;
+            let id = record_id(&key)?;
+            store.next_id = std::cmp::max(store.next_id, id + 1);
+            store.dead_letters.insert(id, serde_json::from_slice(&value)?);
+        }
+        store.tree = Some(tree);
+        Ok(store)
+    }
+
+    pub fn insert(&mut self, msg: &RawMessage, error: String) -> Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let dead_letter = DeadLetter::new(id, msg, error);
+        self.persist(&dead_letter)?;
+        self.dead_letters.insert(id, dead_letter);
+
+        if let Some(max_len) = self.max_len {
+            let excess: Vec<u64> = self.dead_letters.keys()
+                .take(self.dead_letters.len().saturating_sub(max_len))
+                .cloned()
+                .collect();
+            for id in excess {
+                self.remove(id)?;
+            }
+        }
+        Ok(id)
+    }
+
+    pub fn get(&self, id: u64) -> Result<&DeadLetter> {
+        match self.dead_letters.get(&id) {
+            Some(d) => Ok(d),
+            None => Err(anyhow!("Key not present"))
+        }
+    }
+
+    pub fn set_error(&mut self, id: u64, error: String) -> Result<()> {
+        match self.dead_letters.get_mut(&id) {
+            Some(d) => {
+                d.error = error;
+                let d = d.clone();
+                self.persist(&d)
+            },
+            None => Err(anyhow!("Key not present"))
+        }
+    }
+
+    pub fn remove(&mut self, id: u64) -> Result<DeadLetter> {
+        match self.dead_letters.remove(&id) {
+            Some(d) => {
+                if let Some(tree) = &self.tree {
+                    tree.remove(id.to_be_bytes())?;
+                }
+                Ok(d)
+            },
+            None => Err(anyhow!("Key not present"))
+        }
+    }
+
+    pub fn ids(&self) -> Vec<u64> {
+        self.dead_letters.keys().cloned().collect()
+    }
+
+    pub fn purge(&mut self) -> Result<usize> {
+        if let Some(tree) = &self.tree {
+            tree.clear()?;
+        }
+        let purged = self.dead_letters.len();
+        self.dead_letters.clear();
+        Ok(purged)
+    }
+
+    /// Drops the dead letters received before the age limit of the retention policy, returning
+    /// how many were dropped
+    pub fn compact(&mut self, retention: &RetentionPolicy, now: u64) -> Result<usize> {
+        let cutoff = match retention.cutoff(now) {
+            Some(cutoff) => cutoff,
+            None => return Ok(0)
+        };
+
+        let expired: Vec<u64> = self.dead_letters.values()
+            .filter(|dead_letter| dead_letter.metadata.received_at < cutoff)
+            .map(|dead_letter| dead_letter.id)
+            .collect();
+        for id in &expired {
+            self.remove(*id)?;
+        }
+        Ok(expired.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dead_letters.is_empty()
+    }
+
+    /// Dead letters in the order they were stored, starting after the id of `after`
+    pub fn iter_after(&self, after: Option<Cursor>) -> Records<'_, DeadLetter> {
+        let start = after.map_or(Bound::Unbounded, |after| Bound::Excluded(after.id));
+        Box::new(self.dead_letters.range((start, Bound::Unbounded))
+            .map(|(id, dead_letter)| (Cursor::new(dead_letter.metadata.received_at, *id), dead_letter)))
+    }
+
+    fn persist(&self, dead_letter: &DeadLetter) -> Result<()> {
+        if let Some(tree) = &self.tree {
+            tree.insert(dead_letter.id.to_be_bytes(), serde_json::to_vec(dead_letter)?)?;
+        }
+        Ok(())
+    }
+}
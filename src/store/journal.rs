@@ -0,0 +1,223 @@
+use anyhow::{Result, anyhow};
+use crate::models::{MessageMetadata, RawMessage};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::Path
+};
+
+/// A single journal line: the raw masked payload (base64) of a received packet along with its
+/// link, signer and receive time
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JournalEntry {
+    pub metadata: MessageMetadata,
+    pub masked_payload: String,
+}
+
+impl JournalEntry {
+    pub fn new(msg: &RawMessage) -> Self {
+        JournalEntry {
+            metadata: msg.metadata.clone(),
+            masked_payload: base64::encode(&msg.masked_payload),
+        }
+    }
+
+    pub fn to_raw_message(&self) -> Result<RawMessage> {
+        Ok(RawMessage {
+            metadata: self.metadata.clone(),
+            masked_payload: base64::decode(&self.masked_payload)?,
+        })
+    }
+}
+
+/// Append-only log of every packet received from the channel, one JSON entry per line. The
+/// stores can be rebuilt from it by decoding every entry again.
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        truncate_partial_line(&mut file)?;
+
+        Ok(Journal {
+            file
+        })
+    }
+
+    pub fn append(&mut self, msg: &RawMessage) -> Result<()> {
+        let mut line = serde_json::to_vec(&JournalEntry::new(msg))?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Reads every entry of a journal file. A crash mid-append can only leave a partial last line,
+    /// which is skipped. Any other line that can't be parsed means the journal is corrupt.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<RawMessage>> {
+        let reader = BufReader::new(File::open(path)?);
+
+        let mut msgs = Vec::new();
+        let mut invalid: Option<(usize, anyhow::Error)> = None;
+        for (number, line) in reader.split(b'\n').enumerate() {
+            let line = line?;
+            if line.iter().all(|b| b.is_ascii_whitespace()) {
+                continue
+            }
+            if let Some((number, e)) = invalid.take() {
+                return Err(anyhow!("Invalid journal line {}: {}", number + 1, e));
+            }
+
+            let entry = serde_json::from_slice::<JournalEntry>(&line)
+                .map_err(anyhow::Error::from)
+                .and_then(|entry| entry.to_raw_message());
+            match entry {
+                Ok(msg) => msgs.push(msg),
+                Err(e) => invalid = Some((number, e))
+            }
+        }
+
+        if let Some((number, e)) = invalid {
+            println!("Skipping partial last journal line {}: {}", number + 1, e);
+        }
+        Ok(msgs)
+    }
+}
+
+/// Drops a partial last line left by a crash mid-append, so that new entries start on a line of
+/// their own instead of corrupting it
+fn truncate_partial_line(file: &mut File) -> Result<()> {
+    let len = file.metadata()?.len();
+    let mut end = len;
+    let mut buf = [0u8; 4096];
+    while end > 0 {
+        let start = end.saturating_sub(buf.len() as u64);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(position) = chunk.iter().rposition(|b| *b == b'\n') {
+            end = start + position as u64 + 1;
+            break
+        }
+        end = start;
+    }
+
+    if end < len {
+        println!("Dropping partial last journal line ({} bytes)", len - end);
+        file.set_len(end)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::PathBuf};
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("author_console_{}_{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn raw_message(link: &str) -> RawMessage {
+        RawMessage {
+            metadata: MessageMetadata::new(link.to_string(), None, &[]),
+            masked_payload: b"payload".to_vec(),
+        }
+    }
+
+    fn line(link: &str) -> String {
+        format!("{}\n", serde_json::to_string(&JournalEntry::new(&raw_message(link))).unwrap())
+    }
+
+    fn links(msgs: &[RawMessage]) -> Vec<&str> {
+        msgs.iter().map(|msg| msg.metadata.link.as_str()).collect()
+    }
+
+    #[test]
+    fn appended_entries_are_read_back() {
+        let path = journal_path("append");
+        let mut journal = Journal::open(&path).unwrap();
+        journal.append(&raw_message("a:1")).unwrap();
+        journal.append(&raw_message("a:2")).unwrap();
+
+        let msgs = Journal::read(&path).unwrap();
+        assert_eq!(links(&msgs), vec!["a:1", "a:2"]);
+        assert_eq!(msgs[0].masked_payload, b"payload".to_vec());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn partial_last_line_is_skipped() {
+        let path = journal_path("partial_read");
+        fs::write(&path, format!("{}{}{{\"metadata\":", line("a:1"), line("a:2"))).unwrap();
+
+        assert_eq!(links(&Journal::read(&path).unwrap()), vec!["a:1", "a:2"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_middle_line_is_an_error() {
+        let path = journal_path("invalid_read");
+        fs::write(&path, format!("{}not json\n{}", line("a:1"), line("a:2"))).unwrap();
+
+        assert!(Journal::read(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn blank_lines_are_ignored() {
+        let path = journal_path("blank_read");
+        fs::write(&path, format!("\n{}  \n\n{}\n", line("a:1"), line("a:2"))).unwrap();
+
+        assert_eq!(links(&Journal::read(&path).unwrap()), vec!["a:1", "a:2"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn open_truncates_partial_last_line() {
+        let path = journal_path("truncate");
+        let complete = line("a:1");
+        fs::write(&path, format!("{}{{\"metadata\":", complete)).unwrap();
+
+        let mut journal = Journal::open(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete.len() as u64);
+        journal.append(&raw_message("a:2")).unwrap();
+        assert_eq!(links(&Journal::read(&path).unwrap()), vec!["a:1", "a:2"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncate_drops_a_partial_line_longer_than_the_read_buffer() {
+        let path = journal_path("truncate_long");
+        let complete = line("a:1");
+        fs::write(&path, format!("{}{}", complete, "x".repeat(10000))).unwrap();
+
+        let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        truncate_partial_line(&mut file).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete.len() as u64);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncate_drops_a_file_without_any_complete_line() {
+        let path = journal_path("truncate_none");
+        fs::write(&path, "{\"metadata\":").unwrap();
+
+        let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        truncate_partial_line(&mut file).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+
+        truncate_partial_line(&mut file).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,14 +1,42 @@
 pub mod annotation_store;
+pub mod dead_letter_store;
+pub mod journal;
+pub mod page;
 pub mod reading_store;
+pub mod retention;
+pub mod sled_store;
+pub mod storage;
 
 pub use annotation_store::*;
+pub use dead_letter_store::*;
+pub use journal::*;
+pub use page::*;
 pub use reading_store::*;
+pub use retention::*;
+pub use sled_store::*;
+pub use storage::*;
 
 use serde::Deserialize;
+use serde_json::Value;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Locks a shared store. A panic while the lock was held poisons it, the store is used as it was
+/// left rather than failing every later request until the console is restarted.
+pub fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
 #[derive(Debug, Deserialize)]
 pub struct ReadingStoreFilterId {
-    sensor_id: String
+    sensor_id: String,
+    #[serde(default)]
+    public_payload: Option<Value>,
+    #[serde(default)]
+    from: Option<u64>,
+    #[serde(default)]
+    to: Option<u64>,
+    #[serde(flatten)]
+    page: PageRequest,
 }
 
 impl ReadingStoreFilterId {
@@ -16,11 +44,37 @@ impl ReadingStoreFilterId {
         self.sensor_id.clone()
     }
 
+    pub fn get_public_payload(&self) -> Option<&Value> {
+        self.public_payload.as_ref()
+    }
+
+    pub fn get_from(&self) -> u64 {
+        self.from.unwrap_or(0)
+    }
+
+    pub fn get_to(&self) -> u64 {
+        self.to.unwrap_or(u64::MAX)
+    }
+
+    pub fn get_page(&self) -> &PageRequest {
+        &self.page
+    }
+
 }
 
 #[derive(Debug, Deserialize)]
 pub struct AnnotationStoreFilterId {
-    reading_id: String
+    reading_id: String,
+    #[serde(default)]
+    public_payload: Option<Value>,
+    #[serde(default)]
+    from: Option<u64>,
+    #[serde(default)]
+    to: Option<u64>,
+    #[serde(default)]
+    policy: Option<String>,
+    #[serde(flatten)]
+    page: PageRequest,
 }
 
 impl AnnotationStoreFilterId {
@@ -28,5 +82,56 @@ impl AnnotationStoreFilterId {
         self.reading_id.clone()
     }
 
+    pub fn get_public_payload(&self) -> Option<&Value> {
+        self.public_payload.as_ref()
+    }
+
+    pub fn get_from(&self) -> u64 {
+        self.from.unwrap_or(0)
+    }
+
+    pub fn get_to(&self) -> u64 {
+        self.to.unwrap_or(u64::MAX)
+    }
+
+    pub fn get_policy(&self) -> Option<&str> {
+        self.policy.as_deref()
+    }
+
+    pub fn get_page(&self) -> &PageRequest {
+        &self.page
+    }
+
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnnotationFilterRequest {
+    #[serde(flatten)]
+    filter: AnnotationStoreFilter,
+    #[serde(flatten)]
+    page: PageRequest,
 }
 
+impl AnnotationFilterRequest {
+    pub fn get_filter(&self) -> &AnnotationStoreFilter {
+        &self.filter
+    }
+
+    pub fn get_page(&self) -> &PageRequest {
+        &self.page
+    }
+
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeadLetterStoreFilterId {
+    #[serde(default)]
+    id: Option<u64>
+}
+
+impl DeadLetterStoreFilterId {
+    pub fn get_id(&self) -> Option<u64> {
+        self.id
+    }
+
+}
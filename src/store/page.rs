@@ -0,0 +1,139 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+/// Position of a record in a listing, ordered by the time the record was received at and then by
+/// its store id. Sent to clients as an opaque `<received_at>-<id>` string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cursor {
+    pub received_at: u64,
+    pub id: u64,
+}
+
+impl Cursor {
+    pub fn new(received_at: u64, id: u64) -> Self {
+        Cursor { received_at, id }
+    }
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = anyhow::Error;
+
+    fn try_from(cursor: String) -> Result<Self> {
+        let mut parts = cursor.splitn(2, '-');
+        match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+            (Some(Ok(received_at)), Some(Ok(id))) => Ok(Cursor::new(received_at, id)),
+            _ => Err(anyhow!("Invalid cursor {}", cursor))
+        }
+    }
+}
+
+impl From<Cursor> for String {
+    fn from(cursor: Cursor) -> Self {
+        format!("{}-{}", cursor.received_at, cursor.id)
+    }
+}
+
+/// Paging arguments accepted by the list endpoints, `cursor` is the `next_cursor` of the
+/// previous page
+#[derive(Debug, Default, Deserialize)]
+pub struct PageRequest {
+    #[serde(default)]
+    cursor: Option<Cursor>,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+impl PageRequest {
+    pub fn get_cursor(&self) -> Option<Cursor> {
+        self.cursor
+    }
+
+    pub fn get_limit(&self) -> usize {
+        std::cmp::min(std::cmp::max(self.limit.unwrap_or(DEFAULT_PAGE_LIMIT), 1), MAX_PAGE_LIMIT)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<Cursor>,
+}
+
+impl<T> Page<T> {
+    /// Takes up to `limit` items, `next_cursor` points at the last one taken if there are more
+    pub fn collect<I: Iterator<Item = (Cursor, T)>>(mut iter: I, limit: usize) -> Self {
+        let mut items = Vec::new();
+        let mut last = None;
+        while items.len() < limit {
+            match iter.next() {
+                Some((cursor, item)) => {
+                    items.push(item);
+                    last = Some(cursor);
+                },
+                None => break
+            }
+        }
+
+        Page {
+            items,
+            next_cursor: iter.next().and(last)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page_request(json: &str) -> PageRequest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor::new(1602350951342, 41);
+        let encoded = serde_json::to_string(&cursor).unwrap();
+        assert_eq!(encoded, "\"1602350951342-41\"");
+        assert_eq!(serde_json::from_str::<Cursor>(&encoded).unwrap(), cursor);
+        assert_eq!(Cursor::try_from(String::from(cursor)).unwrap(), cursor);
+    }
+
+    #[test]
+    fn invalid_cursors() {
+        for cursor in &["", "1602350951342", "1602350951342-", "-41", "a-41", "1602350951342-b", "1-2-3", "-1-2"] {
+            assert!(Cursor::try_from(cursor.to_string()).is_err(), "{} should be invalid", cursor);
+        }
+        assert!(serde_json::from_str::<PageRequest>("{\"cursor\": \"nope\"}").is_err());
+    }
+
+    #[test]
+    fn last_page_has_no_next_cursor() {
+        let records = (0..5).map(|i| (Cursor::new(i, i), i));
+
+        let page = Page::collect(records.clone(), 3);
+        assert_eq!(page.items, vec![0, 1, 2]);
+        assert_eq!(page.next_cursor, Some(Cursor::new(2, 2)));
+
+        let page = Page::collect(records.clone().skip(3), 3);
+        assert_eq!(page.items, vec![3, 4]);
+        assert_eq!(page.next_cursor, None);
+
+        // A page that ends exactly on the last record has nothing after it either
+        let page = Page::collect(records, 5);
+        assert_eq!(page.items.len(), 5);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn limit_is_clamped() {
+        assert_eq!(page_request("{}").get_limit(), DEFAULT_PAGE_LIMIT);
+        assert_eq!(page_request("{\"limit\": 0}").get_limit(), 1);
+        assert_eq!(page_request("{\"limit\": 42}").get_limit(), 42);
+        assert_eq!(page_request("{\"limit\": 5000}").get_limit(), MAX_PAGE_LIMIT);
+    }
+}
@@ -1,36 +1,120 @@
 use anyhow::{Result, anyhow};
-use std::collections::HashMap;
-use crate::models::{SensorId, Reading};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use crate::models::{SensorId, ReadingId, Reading};
+use crate::store::{record_size, select_expired, time_range, unindex, Cursor, ReadingStorage, Records, RetentionPolicy, TimeIndex};
 
+/// Readings are kept once under a store assigned id, in insertion order, and indexed by the
+/// sensor that published them (ordered by the time they were received at) and by their reading id
 pub struct ReadingStore {
-    readings: HashMap<SensorId, Vec<Reading>>
+    next_id: u64,
+    readings: BTreeMap<u64, Reading>,
+    by_sensor: HashMap<SensorId, TimeIndex>,
+    by_reading_id: HashMap<ReadingId, BTreeSet<u64>>,
+    by_time: TimeIndex,
+    bytes: usize,
 }
 
 impl ReadingStore {
     pub fn new() -> Self {
         ReadingStore {
-            readings: HashMap::<SensorId, Vec<Reading>>::new()
+            next_id: 0,
+            readings: BTreeMap::new(),
+            by_sensor: HashMap::new(),
+            by_reading_id: HashMap::new(),
+            by_time: TimeIndex::new(),
+            bytes: 0,
         }
     }
 
-    pub fn insert(&mut self, sensor_id: &SensorId, reading: Reading) -> Result<()> {
-        match self.readings.get_mut(sensor_id) {
-            Some(annotations) => Ok(annotations.push(reading)),
-            None => {
-                self.readings.insert(sensor_id.clone(), vec![reading]);
-                Ok(())
-            },
+    /// Stores the reading under a given id, used when the ids are assigned by a persistent backend
+    pub fn insert_with_id(&mut self, id: u64, reading: Reading) {
+        self.next_id = std::cmp::max(self.next_id, id + 1);
+        let cursor = Cursor::new(reading.get_metadata().received_at, id);
+        self.by_sensor.entry(reading.get_sensor_id().clone()).or_default().insert(cursor);
+        self.by_reading_id.entry(reading.get_reading_id().clone()).or_default().insert(id);
+        self.by_time.insert(cursor);
+        self.bytes += record_size(&reading);
+        self.readings.insert(id, reading);
+    }
+
+    /// Id of the reading `reading_id` published by `sensor_id`, if it is stored
+    pub fn find(&self, sensor_id: &SensorId, reading_id: &ReadingId) -> Option<u64> {
+        self.by_reading_id.get(reading_id)?
+            .iter()
+            .find(|id| self.readings.get(id).map_or(false, |reading| reading.get_sensor_id() == sensor_id))
+            .cloned()
+    }
+
+    pub fn remove_id(&mut self, id: u64) -> Option<Reading> {
+        let reading = self.readings.remove(&id)?;
+        let cursor = Cursor::new(reading.get_metadata().received_at, id);
+        unindex(&mut self.by_sensor, reading.get_sensor_id(), &cursor);
+        unindex(&mut self.by_reading_id, reading.get_reading_id(), &id);
+        self.by_time.remove(&cursor);
+        self.bytes = self.bytes.saturating_sub(record_size(&reading));
+        Some(reading)
+    }
+
+    /// Ids of the readings outside the retention policy
+    pub fn expired(&self, retention: &RetentionPolicy, now: u64) -> BTreeSet<u64> {
+        let mut evicted = BTreeSet::new();
+        if let Some(max) = retention.max_per_sensor {
+            for ids in self.by_sensor.values() {
+                evicted.extend(ids.iter().take(ids.len().saturating_sub(max)).map(|cursor| cursor.id));
+            }
         }
+        select_expired(&self.by_time, self.bytes, retention, now, &mut evicted, |id| {
+            self.readings.get(&id).map_or(0, record_size)
+        });
+        evicted
+    }
+}
+
+impl ReadingStorage for ReadingStore {
+    fn insert(&mut self, sensor_id: &SensorId, reading: Reading) -> Result<()> {
+        let id = self.next_id;
+        self.insert_with_id(id, reading.with_sensor_id(sensor_id.clone()));
+        Ok(())
+    }
+
+    fn get(&mut self, sensor_id: &SensorId) -> Result<Vec<&Reading>> {
+        Ok(self.iter_range(sensor_id, 0, u64::MAX, None)?.map(|(_, reading)| reading).collect())
     }
 
-    pub fn get(&mut self, sensor_id: &SensorId) -> Result<&Vec<Reading>> {
-        match self.readings.get(sensor_id) {
-            Some(r) => Ok(r),
+    fn iter_range(&mut self, sensor_id: &SensorId, from: u64, to: u64, after: Option<Cursor>) -> Result<Records<'_, Reading>> {
+        let readings = &self.readings;
+        match self.by_sensor.get(sensor_id) {
+            Some(ids) => Ok(Box::new(time_range(ids, from, to, after)
+                .filter_map(move |cursor| readings.get(&cursor.id).map(|reading| (cursor, reading))))),
             None => {
                 Err(anyhow!("Key not present"))
             }
+        }
+    }
 
+    fn get_by_reading_id(&mut self, reading_id: &ReadingId) -> Result<Vec<&Reading>> {
+        let readings = &self.readings;
+        match self.by_reading_id.get(reading_id) {
+            Some(ids) => Ok(ids.iter().filter_map(|id| readings.get(id)).collect()),
+            None => {
+                Err(anyhow!("Key not present"))
+            }
         }
+    }
+
+    fn get_reading(&self, sensor_id: &SensorId, reading_id: &ReadingId) -> Option<&Reading> {
+        self.find(sensor_id, reading_id).and_then(|id| self.readings.get(&id))
+    }
+
+    fn remove(&mut self, sensor_id: &SensorId, reading_id: &ReadingId) -> Result<Option<Reading>> {
+        Ok(self.find(sensor_id, reading_id).and_then(|id| self.remove_id(id)))
+    }
+
+    fn compact(&mut self, retention: &RetentionPolicy, now: u64) -> Result<Vec<Reading>> {
+        Ok(self.expired(retention, now).into_iter().filter_map(|id| self.remove_id(id)).collect())
+    }
 
+    fn is_empty(&self) -> bool {
+        self.readings.is_empty()
     }
 }
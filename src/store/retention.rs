@@ -0,0 +1,227 @@
+use anyhow::Result;
+use crate::models::now_millis;
+use crate::store::{lock, AnnotationStorage, DeadLetterStore, ReadingStorage, TimeIndex};
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+    time::Duration
+};
+use tokio::time::delay_for;
+
+/// Limits on what the stores keep, unset limits are not enforced
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RetentionPolicy {
+    pub max_age: Option<Duration>,
+    pub max_per_sensor: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+impl RetentionPolicy {
+    pub fn new(max_age_secs: Option<u64>, max_per_sensor: Option<u64>, max_bytes: Option<u64>) -> Self {
+        RetentionPolicy {
+            max_age: max_age_secs.map(Duration::from_secs),
+            max_per_sensor: max_per_sensor.map(|max| max as usize),
+            max_bytes: max_bytes.map(|max| max as usize),
+        }
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.max_age.is_none() && self.max_per_sensor.is_none() && self.max_bytes.is_none()
+    }
+
+    /// Records received before this time (ms since the unix epoch) are too old to keep
+    pub fn cutoff(&self, now: u64) -> Option<u64> {
+        self.max_age.map(|age| now.saturating_sub(age.as_millis() as u64))
+    }
+}
+
+/// Adds the records of a store that are too old to `evicted`, followed by the oldest remaining
+/// ones until the store fits in `max_bytes`. `bytes` is the size of the whole store, `evicted` may
+/// already hold records picked for another reason.
+pub(crate) fn select_expired<F: Fn(u64) -> usize>(
+    by_time: &TimeIndex,
+    bytes: usize,
+    retention: &RetentionPolicy,
+    now: u64,
+    evicted: &mut BTreeSet<u64>,
+    size_of: F
+) {
+    let cutoff = retention.cutoff(now);
+    let mut bytes = evicted.iter().fold(bytes, |bytes, id| bytes.saturating_sub(size_of(*id)));
+
+    // The index is oldest first, so once a record is recent enough and the store fits, every
+    // later one will be kept too
+    for cursor in by_time.iter() {
+        let too_old = cutoff.map_or(false, |cutoff| cursor.received_at < cutoff);
+        let too_big = retention.max_bytes.map_or(false, |max| bytes > max);
+        if !too_old && !too_big {
+            break
+        }
+        if evicted.insert(cursor.id) {
+            bytes = bytes.saturating_sub(size_of(cursor.id));
+        }
+    }
+}
+
+/// Enforces a `RetentionPolicy` on the reading, annotation and dead letter stores. Annotations of
+/// evicted readings are evicted with them, dead letters are only subject to the age limit.
+pub struct Compactor {
+    retention: RetentionPolicy,
+    reading_store: Arc<Mutex<dyn ReadingStorage>>,
+    annotation_store: Arc<Mutex<dyn AnnotationStorage>>,
+    dead_letter_store: Arc<Mutex<DeadLetterStore>>,
+}
+
+impl Compactor {
+    pub fn new(
+        retention: RetentionPolicy,
+        reading_store: Arc<Mutex<dyn ReadingStorage>>,
+        annotation_store: Arc<Mutex<dyn AnnotationStorage>>,
+        dead_letter_store: Arc<Mutex<DeadLetterStore>>,
+    ) -> Self {
+        Compactor {
+            retention,
+            reading_store,
+            annotation_store,
+            dead_letter_store
+        }
+    }
+
+    /// Returns the number of readings, annotations and dead letters evicted
+    pub fn compact(&self) -> Result<(usize, usize, usize)> {
+        let now = now_millis();
+        let mut reading_store = lock(&self.reading_store);
+        let mut annotation_store = lock(&self.annotation_store);
+
+        let readings = reading_store.compact(&self.retention, now)?;
+        let mut annotations = annotation_store.compact(&self.retention, now)?;
+        for reading in &readings {
+            // Reading ids are only unique per sensor, the annotations stay while another sensor's
+            // reading with the same id is still stored
+            if reading_store.get_by_reading_id(reading.get_reading_id()).is_err() {
+                annotations += annotation_store.remove_for_reading(reading.get_reading_id())?;
+            }
+        }
+
+        let dead_letters = lock(&self.dead_letter_store)
+            .compact(&self.retention, now)?;
+
+        Ok((readings.len(), annotations, dead_letters))
+    }
+
+    pub fn start(compactor: Self, interval: Duration) -> Result<()> {
+        println!("Store compaction will run every {}s", interval.as_secs());
+        tokio::spawn(async move {
+            loop {
+                delay_for(interval).await;
+                match compactor.compact() {
+                    Ok((0, 0, 0)) => (),
+                    Ok((readings, annotations, dead_letters)) => println!(
+                        "Compaction evicted {} readings, {} annotations and {} dead letters",
+                        readings, annotations, dead_letters
+                    ),
+                    Err(e) => println!("Error compacting stores: {}", e)
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Annotation, MessageMetadata, Reading, ReadingId, SensorId};
+    use crate::store::{AnnotationStore, Cursor, ReadingStore};
+
+    fn reading(sensor_id: &str, reading_id: &str, received_at: u64) -> Reading {
+        let mut metadata = MessageMetadata::new(format!("{}:{}", sensor_id, reading_id), None, &[]);
+        metadata.received_at = received_at;
+        Reading::new()
+            .with_sensor_id(SensorId(sensor_id.to_string()))
+            .with_reading_id(ReadingId(reading_id.to_string()))
+            .with_metadata(metadata)
+    }
+
+    fn index(received_at: &[u64]) -> TimeIndex {
+        received_at.iter().enumerate().map(|(id, received_at)| Cursor::new(*received_at, id as u64)).collect()
+    }
+
+    fn select(retention: RetentionPolicy, now: u64, mut evicted: BTreeSet<u64>) -> Vec<u64> {
+        select_expired(&index(&[100, 200, 300]), 30, &retention, now, &mut evicted, |_| 10);
+        evicted.into_iter().collect()
+    }
+
+    #[test]
+    fn select_expired_by_age() {
+        assert_eq!(select(RetentionPolicy::new(Some(1), None, None), 1250, BTreeSet::new()), vec![0, 1]);
+        assert!(select(RetentionPolicy::new(Some(1), None, None), 1050, BTreeSet::new()).is_empty());
+        assert!(select(RetentionPolicy::default(), 1250, BTreeSet::new()).is_empty());
+    }
+
+    #[test]
+    fn select_expired_by_size() {
+        assert_eq!(select(RetentionPolicy::new(None, None, Some(15)), 0, BTreeSet::new()), vec![0, 1]);
+        assert!(select(RetentionPolicy::new(None, None, Some(30)), 0, BTreeSet::new()).is_empty());
+    }
+
+    #[test]
+    fn select_expired_counts_records_already_evicted() {
+        let evicted = vec![2].into_iter().collect();
+        assert_eq!(select(RetentionPolicy::new(None, None, Some(15)), 0, evicted), vec![0, 2]);
+    }
+
+    #[test]
+    fn readings_are_capped_per_sensor() {
+        let mut store = ReadingStore::new();
+        for (id, reading) in vec![
+            reading("a", "1", 100),
+            reading("b", "1", 150),
+            reading("a", "2", 200),
+            reading("a", "3", 300),
+        ].into_iter().enumerate() {
+            store.insert_with_id(id as u64, reading);
+        }
+
+        let evicted: Vec<u64> = store.expired(&RetentionPolicy::new(None, Some(2), None), 0).into_iter().collect();
+        assert_eq!(evicted, vec![0]);
+        let evicted: Vec<u64> = store.expired(&RetentionPolicy::new(None, Some(1), None), 0).into_iter().collect();
+        assert_eq!(evicted, vec![0, 2]);
+    }
+
+    #[test]
+    fn annotations_stay_while_another_sensor_uses_the_reading_id() {
+        let now = now_millis();
+        let reading_store = Arc::new(Mutex::new(ReadingStore::new()));
+        let annotation_store = Arc::new(Mutex::new(AnnotationStore::new()));
+        {
+            let mut readings = lock(&reading_store);
+            readings.insert_with_id(0, reading("a", "shared", now - 10_000));
+            readings.insert_with_id(1, reading("a", "own", now - 10_000));
+            readings.insert_with_id(2, reading("b", "shared", now));
+        }
+        {
+            let mut annotations = lock(&annotation_store);
+            let mut metadata = MessageMetadata::new("annotation".to_string(), None, &[]);
+            metadata.received_at = now;
+            for (id, reading_id) in ["shared", "own"].iter().enumerate() {
+                let annotation = Annotation::new()
+                    .with_reading_id(ReadingId(reading_id.to_string()))
+                    .with_metadata(metadata.clone());
+                annotations.insert_with_id(id as u64, annotation);
+            }
+        }
+
+        let compactor = Compactor::new(
+            RetentionPolicy::new(Some(5), None, None),
+            reading_store.clone(),
+            annotation_store.clone(),
+            Arc::new(Mutex::new(DeadLetterStore::new()))
+        );
+        assert_eq!(compactor.compact().unwrap(), (2, 1, 0));
+
+        let annotations = lock(&annotation_store);
+        assert_eq!(annotations.find_for_reading(&ReadingId("shared".to_string())), vec![0]);
+        assert!(annotations.find_for_reading(&ReadingId("own".to_string())).is_empty());
+    }
+}
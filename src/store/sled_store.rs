@@ -0,0 +1,177 @@
+use anyhow::{Result, anyhow};
+use crate::models::{SensorId, ReadingId, Reading, Annotation};
+use crate::store::{AnnotationStorage, AnnotationStore, AnnotationStoreFilter, Cursor, ReadingStorage, ReadingStore, Records, RetentionPolicy};
+
+/// Opens the sled database backing the on-disk stores
+pub fn open_sled(path: &str) -> Result<sled::Db> {
+    Ok(sled::open(path)?)
+}
+
+/// Records are keyed by their store id, big endian so the tree keeps them in insertion order
+pub(crate) fn record_id(key: &[u8]) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    if key.len() != bytes.len() {
+        return Err(anyhow!("Invalid record key {}", hex::encode(key)));
+    }
+    bytes.copy_from_slice(key);
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Reading store persisted in a sled tree. Every record is written through to disk on insert,
+/// and the in memory store is rebuilt from the tree when opened.
+pub struct SledReadingStore {
+    db: sled::Db,
+    tree: sled::Tree,
+    readings: ReadingStore,
+}
+
+impl SledReadingStore {
+    pub fn open(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree("readings")?;
+
+        let mut readings = ReadingStore::new();
+        for record in tree.iter() {
+            let (key, value) = record?;
+            readings.insert_with_id(record_id(&key)?, serde_json::from_slice(&value)?);
+        }
+
+        Ok(SledReadingStore {
+            db: db.clone(),
+            tree,
+            readings
+        })
+    }
+}
+
+impl ReadingStorage for SledReadingStore {
+    fn insert(&mut self, sensor_id: &SensorId, reading: Reading) -> Result<()> {
+        // Generated ids are monotonic across restarts, so they double as the in memory store ids
+        let id = self.db.generate_id()?;
+        let reading = reading.with_sensor_id(sensor_id.clone());
+        self.tree.insert(id.to_be_bytes(), serde_json::to_vec(&reading)?)?;
+        self.readings.insert_with_id(id, reading);
+        Ok(())
+    }
+
+    fn get(&mut self, sensor_id: &SensorId) -> Result<Vec<&Reading>> {
+        self.readings.get(sensor_id)
+    }
+
+    fn iter_range(&mut self, sensor_id: &SensorId, from: u64, to: u64, after: Option<Cursor>) -> Result<Records<'_, Reading>> {
+        self.readings.iter_range(sensor_id, from, to, after)
+    }
+
+    fn get_by_reading_id(&mut self, reading_id: &ReadingId) -> Result<Vec<&Reading>> {
+        self.readings.get_by_reading_id(reading_id)
+    }
+
+    fn get_reading(&self, sensor_id: &SensorId, reading_id: &ReadingId) -> Option<&Reading> {
+        self.readings.get_reading(sensor_id, reading_id)
+    }
+
+    fn remove(&mut self, sensor_id: &SensorId, reading_id: &ReadingId) -> Result<Option<Reading>> {
+        match self.readings.find(sensor_id, reading_id) {
+            Some(id) => {
+                self.tree.remove(id.to_be_bytes())?;
+                Ok(self.readings.remove_id(id))
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn compact(&mut self, retention: &RetentionPolicy, now: u64) -> Result<Vec<Reading>> {
+        let mut evicted = Vec::new();
+        for id in self.readings.expired(retention, now) {
+            self.tree.remove(id.to_be_bytes())?;
+            evicted.extend(self.readings.remove_id(id));
+        }
+        Ok(evicted)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.readings.is_empty()
+    }
+}
+
+/// Annotation store persisted in a sled tree, see `SledReadingStore`
+pub struct SledAnnotationStore {
+    db: sled::Db,
+    tree: sled::Tree,
+    annotations: AnnotationStore,
+}
+
+impl SledAnnotationStore {
+    pub fn open(db: &sled::Db) -> Result<Self> {
+        let tree = db.open_tree("annotations")?;
+
+        let mut annotations = AnnotationStore::new();
+        for record in tree.iter() {
+            let (key, value) = record?;
+            annotations.insert_with_id(record_id(&key)?, serde_json::from_slice(&value)?);
+        }
+
+        Ok(SledAnnotationStore {
+            db: db.clone(),
+            tree,
+            annotations
+        })
+    }
+
+    fn remove_ids<I: IntoIterator<Item = u64>>(&mut self, ids: I) -> Result<usize> {
+        let mut removed = 0;
+        for id in ids {
+            self.tree.remove(id.to_be_bytes())?;
+            removed += self.annotations.remove_id(id).map_or(0, |_| 1);
+        }
+        Ok(removed)
+    }
+}
+
+impl AnnotationStorage for SledAnnotationStore {
+    fn insert(&mut self, reading_id: &ReadingId, annotation: Annotation) -> Result<()> {
+        let id = self.db.generate_id()?;
+        let annotation = annotation.with_reading_id(reading_id.clone());
+        self.tree.insert(id.to_be_bytes(), serde_json::to_vec(&annotation)?)?;
+        self.annotations.insert_with_id(id, annotation);
+        Ok(())
+    }
+
+    fn get(&mut self, reading_id: &ReadingId) -> Result<Vec<&Annotation>> {
+        self.annotations.get(reading_id)
+    }
+
+    fn iter_range(&mut self, reading_id: &ReadingId, from: u64, to: u64, after: Option<Cursor>) -> Result<Records<'_, Annotation>> {
+        self.annotations.iter_range(reading_id, from, to, after)
+    }
+
+    fn filter<'a>(&'a mut self, filter: &'a AnnotationStoreFilter, after: Option<Cursor>) -> Result<Records<'a, Annotation>> {
+        Ok(self.annotations.filter(filter, after))
+    }
+
+    fn contains(&self, jti: &str, publisher: Option<&str>) -> bool {
+        self.annotations.contains(jti, publisher)
+    }
+
+    fn remove(&mut self, jti: &str, publisher: Option<&str>) -> Result<Vec<Annotation>> {
+        let mut removed = Vec::new();
+        for id in self.annotations.find(jti, publisher) {
+            self.tree.remove(id.to_be_bytes())?;
+            removed.extend(self.annotations.remove_id(id));
+        }
+        Ok(removed)
+    }
+
+    fn compact(&mut self, retention: &RetentionPolicy, now: u64) -> Result<usize> {
+        let expired = self.annotations.expired(retention, now);
+        self.remove_ids(expired)
+    }
+
+    fn remove_for_reading(&mut self, reading_id: &ReadingId) -> Result<usize> {
+        let ids = self.annotations.find_for_reading(reading_id);
+        self.remove_ids(ids)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.annotations.is_empty()
+    }
+}
\ No newline at end of file
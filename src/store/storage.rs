@@ -0,0 +1,98 @@
+use anyhow::Result;
+use crate::models::{SensorId, ReadingId, Reading, Annotation};
+use crate::store::{AnnotationStoreFilter, Cursor, RetentionPolicy};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+use std::ops::Bound;
+
+/// Records of a store in listing order, along with their position in it
+pub type Records<'a, T> = Box<dyn Iterator<Item = (Cursor, &'a T)> + 'a>;
+
+/// Store ids ordered by the time their record was received at
+pub(crate) type TimeIndex = BTreeSet<Cursor>;
+
+/// Positions of the records received between `from` and `to` (inclusive) that come after the
+/// cursor, oldest first
+pub(crate) fn time_range(index: &TimeIndex, from: u64, to: u64, after: Option<Cursor>) -> Box<dyn Iterator<Item = Cursor> + '_> {
+    let start = match after {
+        Some(after) if after >= Cursor::new(from, 0) => Bound::Excluded(after),
+        _ => Bound::Included(Cursor::new(from, 0))
+    };
+    let end = Cursor::new(to, u64::MAX);
+
+    // BTreeSet::range panics on an inverted range, which simply matches nothing here
+    match start {
+        Bound::Included(start) | Bound::Excluded(start) if start > end => Box::new(std::iter::empty()),
+        _ => Box::new(index.range((start, Bound::Included(end))).cloned())
+    }
+}
+
+/// Approximate size of a record, as stored on disk
+pub(crate) fn record_size<T: Serialize>(record: &T) -> usize {
+    serde_json::to_vec(record).map_or(0, |bytes| bytes.len())
+}
+
+/// Drops an id from an index, along with its key once no ids are left
+pub(crate) fn unindex<K: Hash + Eq, T: Ord>(index: &mut HashMap<K, BTreeSet<T>>, key: &K, id: &T) {
+    if let Some(ids) = index.get_mut(key) {
+        ids.remove(id);
+        if ids.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+/// Storage backend for readings, keyed by the sensor that published them
+pub trait ReadingStorage: Send {
+    fn insert(&mut self, sensor_id: &SensorId, reading: Reading) -> Result<()>;
+
+    fn get(&mut self, sensor_id: &SensorId) -> Result<Vec<&Reading>>;
+
+    /// Readings of the sensor received between `from` and `to` (inclusive, ms since the unix epoch),
+    /// oldest first, starting after `after`
+    fn iter_range(&mut self, sensor_id: &SensorId, from: u64, to: u64, after: Option<Cursor>) -> Result<Records<'_, Reading>>;
+
+    /// Reading ids are chosen by the sensors, so more than one sensor may have used the same one
+    fn get_by_reading_id(&mut self, reading_id: &ReadingId) -> Result<Vec<&Reading>>;
+
+    /// Readings are unique per sensor and reading id, returns the stored one
+    fn get_reading(&self, sensor_id: &SensorId, reading_id: &ReadingId) -> Option<&Reading>;
+
+    fn remove(&mut self, sensor_id: &SensorId, reading_id: &ReadingId) -> Result<Option<Reading>>;
+
+    /// Evicts the readings outside the retention policy and returns them
+    fn compact(&mut self, retention: &RetentionPolicy, now: u64) -> Result<Vec<Reading>>;
+
+    fn is_empty(&self) -> bool;
+}
+
+/// Storage backend for annotations, keyed by the reading they annotate
+pub trait AnnotationStorage: Send {
+    fn insert(&mut self, reading_id: &ReadingId, annotation: Annotation) -> Result<()>;
+
+    fn get(&mut self, reading_id: &ReadingId) -> Result<Vec<&Annotation>>;
+
+    /// Annotations of the reading received between `from` and `to` (inclusive, ms since the unix
+    /// epoch), oldest first, starting after `after`
+    fn iter_range(&mut self, reading_id: &ReadingId, from: u64, to: u64, after: Option<Cursor>) -> Result<Records<'_, Annotation>>;
+
+    /// Annotations matching the filter in the order they were stored, starting after `after`
+    fn filter<'a>(&'a mut self, filter: &'a AnnotationStoreFilter, after: Option<Cursor>) -> Result<Records<'a, Annotation>>;
+
+    /// Annotations are unique per `jti` and publisher, so that one publisher can't claim the `jti`
+    /// of another
+    fn contains(&self, jti: &str, publisher: Option<&str>) -> bool;
+
+    /// Removes the annotations with the `jti` published by `publisher`
+    fn remove(&mut self, jti: &str, publisher: Option<&str>) -> Result<Vec<Annotation>>;
+
+    /// Evicts the annotations outside the age and size limits of the retention policy, returns
+    /// the number evicted
+    fn compact(&mut self, retention: &RetentionPolicy, now: u64) -> Result<usize>;
+
+    /// Removes every annotation of a reading, returns the number removed
+    fn remove_for_reading(&mut self, reading_id: &ReadingId) -> Result<usize>;
+
+    fn is_empty(&self) -> bool;
+}
\ No newline at end of file
@@ -6,16 +6,36 @@ use iota_streams::app::transport::{
         MsgId,
     }
 };
-use iota_streams::app_channels::api::tangle::{Address, Author, ChannelAddress, MessageContent};
+use iota_streams::app_channels::api::{psk_from_seed, pskid_from_psk};
+use iota_streams::app_channels::api::tangle::{Address, Author, Bytes, ChannelAddress, MessageContent};
+use iota_streams::core::psk::{Psk, PskId, PSKID_SIZE};
 use iota_streams::core_edsig::signature::ed25519::PublicKey;
 
+use std::collections::HashMap;
 use std::str::FromStr;
-use crate::models::{Reading, Annotation};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::models::{MessageMetadata, RawMessage, SubscriptionRequest, SubscriptionResult, BatchSubscriptionResponse, PskGroup};
 
 pub struct ChannelAuthor {
     author: Author<Client>,
     announcement_id: Address,
     channel_address: ChannelAddress,
+    subscribers: Vec<PublicKey>,
+    psk_groups: HashMap<String, PskId>,
+}
+
+/// Exported form of a `ChannelAuthor`. The Streams author state, including the PSKs, is encrypted
+/// with the provided password by the Streams library. The announcement link, subscriber keys and
+/// the ids of the PSK groups are kept alongside it in the clear.
+#[derive(Serialize, Deserialize)]
+struct ChannelAuthorState {
+    announcement_id: (String, String),
+    author: String,
+    #[serde(default)]
+    subscribers: Vec<String>,
+    #[serde(default)]
+    psk_groups: HashMap<String, String>,
 }
 
 impl ChannelAuthor {
@@ -31,10 +51,83 @@ impl ChannelAuthor {
         Ok(ChannelAuthor {
             author: author,
             announcement_id: announcement_id.clone(),
-            channel_address: announcement_id.appinst.clone()
+            channel_address: announcement_id.appinst.clone(),
+            subscribers: Vec::new(),
+            psk_groups: HashMap::new(),
         })
     }
 
+    pub fn recover(seed: &str, announcement_link: &str, node: &str) -> Result<ChannelAuthor> {
+        let announcement_id = parse_address(announcement_link)?;
+
+        // Create Client instance
+        let client = Client::new_from_url(node);
+
+        // Rebuild the Author from its seed without publishing a new announcement, then walk the
+        // channel to bring the sequence states up to date
+        let mut author = Author::recover(seed, &announcement_id, true, client)?;
+        author.sync_state();
+
+        Ok(ChannelAuthor {
+            author: author,
+            announcement_id: announcement_id.clone(),
+            channel_address: announcement_id.appinst.clone(),
+            subscribers: Vec::new(),
+            psk_groups: HashMap::new(),
+        })
+    }
+
+    pub fn import(bytes: &[u8], password: &str, node: &str) -> Result<ChannelAuthor> {
+        let state: ChannelAuthorState = serde_json::from_slice(bytes)?;
+        let author_bytes = base64::decode(&state.author)?;
+
+        // Create Client instance
+        let client = Client::new_from_url(node);
+
+        // Rebuild the Author instance, including its subscribers and sequence states
+        let author = Author::import(&author_bytes, password, client)?;
+        let announcement_id = Address::from_str(&state.announcement_id.0, &state.announcement_id.1)
+            .map_err(|_| anyhow!("Error parsing stored announcement id"))?;
+
+        let mut subscribers = Vec::new();
+        for pk in state.subscribers {
+            subscribers.push(PublicKey::from_bytes(&hex::decode(pk)?)?);
+        }
+
+        // The PSKs themselves are restored with the encrypted Author state, only the group names and
+        // PSK ids are kept here. Older state files stored the PSK instead of its id.
+        let mut psk_groups = HashMap::new();
+        for (name, id) in state.psk_groups {
+            let bytes = hex::decode(id)?;
+            let pskid = if bytes.len() == PSKID_SIZE {
+                PskId::clone_from_slice(&bytes)
+            } else {
+                pskid_from_psk(&Psk::clone_from_slice(&bytes))
+            };
+            psk_groups.insert(name, pskid);
+        }
+
+        Ok(ChannelAuthor {
+            author: author,
+            announcement_id: announcement_id.clone(),
+            channel_address: announcement_id.appinst.clone(),
+            subscribers,
+            psk_groups,
+        })
+    }
+
+    pub fn export(&self, password: &str) -> Result<Vec<u8>> {
+        let state = ChannelAuthorState {
+            announcement_id: self.get_announcement_id()?,
+            author: base64::encode(self.author.export(password)?),
+            subscribers: self.subscribers.iter().map(|pk| hex::encode(pk.as_bytes())).collect(),
+            psk_groups: self.psk_groups.iter()
+                .map(|(name, pskid)| (name.clone(), hex::encode(pskid.as_slice())))
+                .collect(),
+        };
+        Ok(serde_json::to_vec(&state)?)
+    }
+
     pub fn get_channel_address(&self) -> Result<String> {
         let channel_address = &self.channel_address.to_string();
         Ok(String::from_str(channel_address).unwrap())
@@ -47,6 +140,167 @@ impl ChannelAuthor {
     }
 
     pub fn subscribe(&mut self, link: &str, pk: &Vec<u8>) -> Result<Address> {
+        let pk = self.receive_subscription(link, pk)?;
+        let keyload = self.author.send_keyload(
+            &self.announcement_id,
+            &vec![],
+            &vec![pk]
+        )?;
+
+        self.add_subscribers(vec![pk]);
+
+        // Return the sequence message link
+        Ok(keyload.1.unwrap())
+    }
+
+    pub fn subscribe_batch(&mut self, requests: &Vec<SubscriptionRequest>) -> Result<BatchSubscriptionResponse> {
+        let mut results = Vec::new();
+        let mut pks = Vec::new();
+
+        // Process every subscription first, then cover the whole group with a single keyload
+        for request in requests {
+            let received = hex::decode(&request.pk)
+                .map_err(|e| anyhow!(e))
+                .and_then(|pk| self.receive_subscription(&request.msgid, &pk));
+            match received {
+                Ok(pk) => {
+                    pks.push(pk);
+                    results.push(SubscriptionResult::subscribed(request));
+                },
+                Err(e) => results.push(SubscriptionResult::failed(request, e.to_string()))
+            }
+        }
+
+        let mut keyload_link = None;
+        if !pks.is_empty() {
+            let keyload = self.author.send_keyload(&self.announcement_id, &vec![], &pks)?;
+            self.add_subscribers(pks);
+            keyload_link = Some(keyload.1
+                .ok_or_else(|| anyhow!("No sequence link returned for keyload"))?
+                .to_string());
+        }
+
+        Ok(BatchSubscriptionResponse {
+            keyload_link,
+            results
+        })
+    }
+
+    pub fn revoke(&mut self, pk: &Vec<u8>) -> Result<Address> {
+        let pk = PublicKey::from_bytes(pk)?;
+
+        // The Author keeps the authoritative subscriber set, which also covers subscribers that were
+        // restored from an older state file or by recovery and so are missing from `subscribers`
+        self.author.remove_subscriber(pk)
+            .map_err(|e| anyhow!("Not a subscriber of this channel: {}: {}", hex::encode(pk.as_bytes()), e))?;
+        self.subscribers.retain(|subscriber| subscriber != &pk);
+
+        // Rekey the channel for every remaining subscriber and every stored PSK
+        let keyload = self.author.send_keyload_for_everyone(&self.announcement_id)?;
+
+        // Return the sequence message link
+        keyload.1.ok_or_else(|| anyhow!("No sequence link returned for keyload"))
+    }
+
+    pub fn create_psk_group(&mut self, name: &str) -> Result<PskGroup> {
+        if self.psk_groups.contains_key(name) {
+            return Err(anyhow!("PSK group already exists: {}", name));
+        }
+
+        let seed = rand::thread_rng().gen::<[u8; 32]>();
+        let psk = psk_from_seed(&seed);
+        let pskid = pskid_from_psk(&psk);
+        self.author.store_psk(pskid, psk);
+        self.psk_groups.insert(name.to_string(), pskid);
+
+        // The PSK can't be handed out if the keyload fails, drop it so the group can be created again
+        let keyload_link = match self.send_psk_keyload(&vec![name.to_string()]) {
+            Ok(keyload_link) => keyload_link,
+            Err(e) => {
+                self.psk_groups.remove(name);
+                if let Err(remove_error) = self.author.remove_psk(pskid) {
+                    println!("Error removing PSK of group {}: {}", name, remove_error);
+                }
+                return Err(e)
+            }
+        };
+
+        // The PSK is only ever returned here, so it can be provisioned onto the devices of the group
+        Ok(PskGroup {
+            name: name.to_string(),
+            pskid: hex::encode(pskid.as_slice()),
+            psk: Some(hex::encode(psk.as_slice())),
+            keyload_link: Some(keyload_link.to_string()),
+        })
+    }
+
+    pub fn get_psk_groups(&self) -> Vec<PskGroup> {
+        self.psk_groups.iter()
+            .map(|(name, pskid)| PskGroup {
+                name: name.clone(),
+                pskid: hex::encode(pskid.as_slice()),
+                psk: None,
+                keyload_link: None,
+            })
+            .collect()
+    }
+
+    pub fn send_psk_keyload(&mut self, names: &Vec<String>) -> Result<Address> {
+        let mut pskids = Vec::new();
+        for name in names {
+            match self.psk_groups.get(name) {
+                Some(pskid) => pskids.push(*pskid),
+                None => return Err(anyhow!("PSK group not found: {}", name))
+            }
+        }
+
+        let keyload = self.author.send_keyload(&self.announcement_id, &pskids, &vec![])?;
+
+        // Return the sequence message link
+        keyload.1.ok_or_else(|| anyhow!("No sequence link returned for keyload"))
+    }
+
+    pub fn send_signed_packet(&mut self, link_to: &Option<String>, public_payload: &[u8], masked_payload: &[u8]) -> Result<Address> {
+        let link_to = self.resolve_link(link_to)?;
+        let msg = self.author.send_signed_packet(
+            &link_to,
+            &Bytes(public_payload.to_vec()),
+            &Bytes(masked_payload.to_vec())
+        )?;
+
+        // Return the packet message link
+        Ok(msg.0)
+    }
+
+    pub fn send_tagged_packet(&mut self, link_to: &Option<String>, public_payload: &[u8], masked_payload: &[u8]) -> Result<Address> {
+        let link_to = self.resolve_link(link_to)?;
+        let msg = self.author.send_tagged_packet(
+            &link_to,
+            &Bytes(public_payload.to_vec()),
+            &Bytes(masked_payload.to_vec())
+        )?;
+
+        // Return the packet message link
+        Ok(msg.0)
+    }
+
+    /// Packets link to the announcement by default, or to a keyload branch given either as a full
+    /// `appinst:msgid` link or as a msgid within this channel
+    fn resolve_link(&self, link_to: &Option<String>) -> Result<Address> {
+        match link_to {
+            None => Ok(self.announcement_id.clone()),
+            Some(link) if link.contains(':') => parse_address(link),
+            Some(link) => match MsgId::from_str(link) {
+                Ok(msgid) => Ok(Address {
+                    appinst: self.channel_address.clone(),
+                    msgid,
+                }),
+                Err(_) => Err(anyhow!("Error getting msgid from provided link: {}", link))
+            }
+        }
+    }
+
+    fn receive_subscription(&mut self, link: &str, pk: &Vec<u8>) -> Result<PublicKey> {
         match MsgId::from_str(link) {
             Ok(msgid) => {
                 self.
@@ -57,14 +311,7 @@ impl ChannelAuthor {
                             msgid,
                         })?;
 
-                let keyload = self.author.send_keyload(
-                    &self.announcement_id,
-                    &vec![],
-                    &vec![PublicKey::from_bytes(pk).unwrap()]
-                )?;
-
-                // Return the sequence message link
-                Ok(keyload.1.unwrap())
+                Ok(PublicKey::from_bytes(pk)?)
             },
             Err(_) => {
                 Err(anyhow!("Error getting msgid from provided link: {}", link))
@@ -72,25 +319,33 @@ impl ChannelAuthor {
         }
     }
 
-    pub fn get_next_msgs(&mut self) -> Result<Vec<(Option<Reading>, Option<Annotation>)>> {
+    fn add_subscribers(&mut self, pks: Vec<PublicKey>) {
+        for pk in pks {
+            if !self.subscribers.contains(&pk) {
+                self.subscribers.push(pk);
+            }
+        }
+    }
+
+    pub fn get_next_msgs(&mut self) -> Result<Vec<RawMessage>> {
         let mut found_msgs = Vec::new();
 
         let response = self.author.fetch_next_msgs();
         for msg in response {
+            let link = format!("{}:{}", msg.link.appinst, msg.link.msgid);
             match msg.body {
-                MessageContent::SignedPacket {pk: _, public_payload: _, masked_payload: m} => {
-                    let reading: serde_json::Result<Reading> = serde_json::from_slice(&m.0);
-                    match reading {
-                        Ok(r) => found_msgs.push((Some(r), None)),
-                        Err(_) => {
-                            let annotation: serde_json::Result<Annotation> = serde_json::from_slice(&m.0);
-                            match annotation {
-                                Ok(a) => found_msgs.push((None, Some(a))),
-                                Err(_) => { println!("Error deserializing message") }
-                            };
-                        }
-                    }
-                }
+                MessageContent::SignedPacket {pk, public_payload: p, masked_payload: m} => {
+                    found_msgs.push(RawMessage {
+                        metadata: MessageMetadata::new(link, Some(hex::encode(pk.as_bytes())), &p.0),
+                        masked_payload: m.0,
+                    })
+                },
+                MessageContent::TaggedPacket {public_payload: p, masked_payload: m} => {
+                    found_msgs.push(RawMessage {
+                        metadata: MessageMetadata::new(link, None, &p.0),
+                        masked_payload: m.0,
+                    })
+                },
                 _ => println!("Message type not supported")
             }
         }
@@ -98,3 +353,15 @@ impl ChannelAuthor {
         Ok(found_msgs)
     }
 }
+
+/// Parses an `appinst:msgid` formatted link into a Streams `Address`
+fn parse_address(link: &str) -> Result<Address> {
+    let mut parts = link.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(appinst), Some(msgid)) => {
+            Address::from_str(appinst, msgid)
+                .map_err(|_| anyhow!("Error parsing address from provided link: {}", link))
+        },
+        _ => Err(anyhow!("Link must be formatted as <appinst>:<msgid>: {}", link))
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::{Result, anyhow};
+use crate::models::{BatchSubscriptionResponse, PskGroup, RawMessage, SubscriptionRequest};
+use crate::streams::ChannelAuthor;
+use iota_streams::app_channels::api::tangle::Address;
+use std::panic::{self, AssertUnwindSafe};
+use tokio::sync::{mpsc, oneshot};
+
+type AuthorJob = Box<dyn FnOnce(&mut ChannelAuthor) + Send>;
+
+/// Handle to a `ChannelAuthor` owned by its own task. Requests are queued to the task and
+/// answered over a oneshot channel. The task runs one request at a time, so a caller still waits
+/// for the Tangle round trips queued ahead of it, but API requests are queued separately and run
+/// ahead of message polls, so they wait for at most the poll in progress. The immutable channel
+/// identifiers are answered without touching the task at all.
+#[derive(Clone)]
+pub struct AuthorHandle {
+    requests: mpsc::UnboundedSender<AuthorJob>,
+    polls: mpsc::UnboundedSender<AuthorJob>,
+    channel_address: String,
+    announcement_id: (String, String),
+}
+
+impl AuthorHandle {
+    pub fn spawn(author: ChannelAuthor) -> Result<AuthorHandle> {
+        let channel_address = author.get_channel_address()?;
+        let announcement_id = author.get_announcement_id()?;
+        let (requests, mut request_receiver) = mpsc::unbounded_channel::<AuthorJob>();
+        let (polls, mut poll_receiver) = mpsc::unbounded_channel::<AuthorJob>();
+
+        tokio::spawn(async move {
+            let mut author = author;
+            loop {
+                // Pending requests are drained before a poll is picked up, select! alone would
+                // choose at random between both queues when they are ready
+                let job = match request_receiver.try_recv() {
+                    Ok(job) => job,
+                    Err(_) => tokio::select! {
+                        Some(job) = request_receiver.recv() => job,
+                        Some(job) = poll_receiver.recv() => job,
+                        else => break
+                    }
+                };
+
+                // Streams calls are blocking network I/O, keep them off the async workers. A
+                // panicking job drops its response sender, the caller sees an error and the
+                // author keeps serving other requests.
+                tokio::task::block_in_place(|| {
+                    if panic::catch_unwind(AssertUnwindSafe(|| job(&mut author))).is_err() {
+                        println!("Author request panicked");
+                    }
+                });
+            }
+        });
+
+        Ok(AuthorHandle {
+            requests,
+            polls,
+            channel_address,
+            announcement_id
+        })
+    }
+
+    async fn call<R, F>(&self, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut ChannelAuthor) -> R + Send + 'static
+    {
+        Self::queue(&self.requests, f).await
+    }
+
+    async fn queue<R, F>(queue: &mpsc::UnboundedSender<AuthorJob>, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut ChannelAuthor) -> R + Send + 'static
+    {
+        let (response, receiver) = oneshot::channel();
+        queue.send(Box::new(move |author: &mut ChannelAuthor| {
+            let _ = response.send(f(author));
+        })).map_err(|_| anyhow!("Author unavailable"))?;
+
+        receiver.await.map_err(|_| anyhow!("Author request failed"))
+    }
+
+    pub fn get_channel_address(&self) -> Result<String> {
+        Ok(self.channel_address.clone())
+    }
+
+    pub fn get_announcement_id(&self) -> Result<(String, String)> {
+        Ok(self.announcement_id.clone())
+    }
+
+    pub async fn subscribe(&self, link: String, pk: Vec<u8>) -> Result<Address> {
+        self.call(move |author| author.subscribe(&link, &pk)).await?
+    }
+
+    pub async fn subscribe_batch(&self, requests: Vec<SubscriptionRequest>) -> Result<BatchSubscriptionResponse> {
+        self.call(move |author| author.subscribe_batch(&requests)).await?
+    }
+
+    pub async fn revoke(&self, pk: Vec<u8>) -> Result<Address> {
+        self.call(move |author| author.revoke(&pk)).await?
+    }
+
+    pub async fn create_psk_group(&self, name: String) -> Result<PskGroup> {
+        self.call(move |author| author.create_psk_group(&name)).await?
+    }
+
+    pub async fn get_psk_groups(&self) -> Result<Vec<PskGroup>> {
+        self.call(|author| author.get_psk_groups()).await
+    }
+
+    pub async fn send_psk_keyload(&self, names: Vec<String>) -> Result<Address> {
+        self.call(move |author| author.send_psk_keyload(&names)).await?
+    }
+
+    pub async fn send_signed_packet(&self, link_to: Option<String>, public_payload: Vec<u8>, masked_payload: Vec<u8>) -> Result<Address> {
+        self.call(move |author| author.send_signed_packet(&link_to, &public_payload, &masked_payload)).await?
+    }
+
+    pub async fn send_tagged_packet(&self, link_to: Option<String>, public_payload: Vec<u8>, masked_payload: Vec<u8>) -> Result<Address> {
+        self.call(move |author| author.send_tagged_packet(&link_to, &public_payload, &masked_payload)).await?
+    }
+
+    pub async fn export(&self, password: String) -> Result<Vec<u8>> {
+        self.call(move |author| author.export(&password)).await?
+    }
+
+    pub async fn get_next_msgs(&self) -> Result<Vec<RawMessage>> {
+        Self::queue(&self.polls, |author| author.get_next_msgs()).await?
+    }
+}
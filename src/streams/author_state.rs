@@ -0,0 +1,67 @@
+use anyhow::Result;
+use crate::streams::{AuthorHandle, ChannelAuthor};
+use std::{
+    fs,
+    path::PathBuf,
+    time::Duration
+};
+use tokio::time::delay_for;
+
+/// Location and password of the exported `ChannelAuthor` state on disk
+#[derive(Clone)]
+pub struct AuthorStateFile {
+    path: PathBuf,
+    password: String,
+}
+
+impl AuthorStateFile {
+    pub fn new(path: &str, password: &str) -> Self {
+        AuthorStateFile {
+            path: PathBuf::from(path),
+            password: password.to_string(),
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn get_path(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    pub fn load(&self, node: &str) -> Result<ChannelAuthor> {
+        let bytes = fs::read(&self.path)?;
+        ChannelAuthor::import(&bytes, &self.password, node)
+    }
+
+    pub fn save(&self, author: &ChannelAuthor) -> Result<()> {
+        self.write(&author.export(&self.password)?)
+    }
+
+    pub async fn save_from(&self, author: &AuthorHandle) -> Result<()> {
+        let bytes = author.export(self.password.clone()).await?;
+        self.write(&bytes)
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<()> {
+        // Write to a temporary file first so a crash mid-write can't corrupt the last good state
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, bytes)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    pub fn start_autosave(state_file: Self, author: AuthorHandle, interval: Duration) -> Result<()> {
+        println!("Author state will be saved to {} every {}s", state_file.get_path(), interval.as_secs());
+        tokio::spawn(async move {
+            loop {
+                delay_for(interval).await;
+                if let Err(e) = state_file.save_from(&author).await {
+                    println!("Error saving author state: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::{Result, anyhow};
+use crate::models::{Reading, Annotation, MessageEnvelope, RawMessage, ChannelPayload};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Decodes the body of an envelope of a given version
+pub type PayloadHandler = Box<dyn Fn(u32, Value) -> Result<ChannelPayload> + Send + Sync>;
+
+/// Registry of the message kinds the console understands, keyed by the envelope `type`
+pub struct MessageDecoder {
+    handlers: HashMap<String, PayloadHandler>,
+}
+
+impl MessageDecoder {
+    pub fn new() -> Self {
+        let mut decoder = MessageDecoder {
+            handlers: HashMap::new()
+        };
+
+        decoder.register("reading", Box::new(|version, body| {
+            match version {
+                1 => Ok(ChannelPayload::Reading(serde_json::from_value::<Reading>(body)?)),
+                _ => Err(anyhow!("Unsupported reading version: {}", version))
+            }
+        }));
+        decoder.register("annotation", Box::new(|version, body| {
+            match version {
+                1 => Ok(ChannelPayload::Annotation(serde_json::from_value::<Annotation>(body)?)),
+                _ => Err(anyhow!("Unsupported annotation version: {}", version))
+            }
+        }));
+
+        decoder
+    }
+
+    pub fn register(&mut self, kind: &str, handler: PayloadHandler) {
+        self.handlers.insert(kind.to_string(), handler);
+    }
+
+    pub fn decode(&self, msg: &RawMessage) -> Result<ChannelPayload> {
+        let value: Value = serde_json::from_slice(&msg.masked_payload)?;
+
+        let payload = if value.get("type").is_some() {
+            let envelope: MessageEnvelope = serde_json::from_value(value)?;
+            match self.handlers.get(&envelope.kind) {
+                Some(handler) => handler(envelope.version, envelope.body)?,
+                None => return Err(anyhow!("Unknown message type: {}", envelope.kind))
+            }
+        } else {
+            Self::decode_legacy(value)?
+        };
+
+        Ok(payload.with_metadata(msg.metadata.clone()))
+    }
+
+    /// Bare JSON records sent before envelopes were introduced. The kind is decided by the fields
+    /// that only one of the record types has, rather than by which type happens to deserialize.
+    fn decode_legacy(value: Value) -> Result<ChannelPayload> {
+        if value.get("annotation").is_some() {
+            Ok(ChannelPayload::Annotation(serde_json::from_value(value)?))
+        } else if value.get("sensor_id").is_some() {
+            Ok(ChannelPayload::Reading(serde_json::from_value(value)?))
+        } else {
+            Err(anyhow!("Payload is neither a reading nor an annotation"))
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{MessageMetadata, ReadingId, SensorId};
+    use serde_json::json;
+
+    fn raw_message(payload: Value) -> RawMessage {
+        RawMessage {
+            metadata: MessageMetadata::new("appinst:msgid".to_string(), None, &[]),
+            masked_payload: serde_json::to_vec(&payload).unwrap(),
+        }
+    }
+
+    fn reading() -> Value {
+        serde_json::to_value(Reading::new()
+            .with_sensor_id(SensorId("sensor".to_string()))
+            .with_reading_id(ReadingId("01".to_string()))).unwrap()
+    }
+
+    fn annotation() -> Value {
+        serde_json::to_value(Annotation::new().with_reading_id(ReadingId("01".to_string()))).unwrap()
+    }
+
+    #[test]
+    fn envelopes_are_decoded_by_type() {
+        let decoder = MessageDecoder::new();
+
+        let msg = raw_message(json!({ "type": "reading", "version": 1, "body": reading() }));
+        assert!(matches!(decoder.decode(&msg).unwrap(), ChannelPayload::Reading(_)));
+
+        let msg = raw_message(json!({ "type": "annotation", "version": 1, "body": annotation() }));
+        assert!(matches!(decoder.decode(&msg).unwrap(), ChannelPayload::Annotation(_)));
+    }
+
+    #[test]
+    fn unknown_envelope_type_is_an_error() {
+        let msg = raw_message(json!({ "type": "calibration", "version": 1, "body": reading() }));
+        let error = MessageDecoder::new().decode(&msg).err().unwrap();
+        assert_eq!(error.to_string(), "Unknown message type: calibration");
+    }
+
+    #[test]
+    fn unsupported_envelope_version_is_an_error() {
+        let msg = raw_message(json!({ "type": "reading", "version": 2, "body": reading() }));
+        let error = MessageDecoder::new().decode(&msg).err().unwrap();
+        assert_eq!(error.to_string(), "Unsupported reading version: 2");
+    }
+
+    #[test]
+    fn legacy_annotation_that_fits_a_reading_is_an_annotation() {
+        // Carries every field a reading needs as well as the annotation
+        let mut payload = annotation();
+        for (key, value) in reading().as_object().unwrap() {
+            payload.as_object_mut().unwrap().entry(key.clone()).or_insert(value.clone());
+        }
+        assert!(serde_json::from_value::<Reading>(payload.clone()).is_ok());
+
+        match MessageDecoder::new().decode(&raw_message(payload)).unwrap() {
+            ChannelPayload::Annotation(annotation) => assert_eq!(annotation.get_reading_id().0, "01"),
+            ChannelPayload::Reading(_) => panic!("Annotation decoded as a reading")
+        }
+    }
+
+    #[test]
+    fn legacy_reading() {
+        let msg = raw_message(reading());
+        assert!(matches!(MessageDecoder::new().decode(&msg).unwrap(), ChannelPayload::Reading(_)));
+    }
+
+    #[test]
+    fn payload_that_is_neither_is_an_error() {
+        let error = MessageDecoder::new().decode(&raw_message(json!({ "reading_id": "01" }))).err().unwrap();
+        assert_eq!(error.to_string(), "Payload is neither a reading nor an annotation");
+
+        let msg = RawMessage { masked_payload: b"not json".to_vec(), ..raw_message(Value::Null) };
+        assert!(MessageDecoder::new().decode(&msg).is_err());
+    }
+}
@@ -0,0 +1,456 @@
+use anyhow::{Result, anyhow};
+use crate::models::{now_millis, Reading, RawMessage, ChannelPayload};
+use crate::store::{lock, AnnotationStorage, DeadLetterStore, Journal, ReadingStorage, RetentionPolicy};
+use crate::streams::MessageDecoder;
+use serde::Serialize;
+use std::{
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex}
+};
+
+/// How readings whose `sensor_id` does not match the public key that signed them, or that were not
+/// signed at all, are handled
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SignerPolicy {
+    Off,
+    Flag,
+    Reject,
+}
+
+impl SignerPolicy {
+    /// Flag is used if no policy is configured, unknown policies are an error
+    pub fn from_config(policy: Option<&str>) -> Result<Self> {
+        match policy {
+            Some("off") => Ok(SignerPolicy::Off),
+            Some("reject") => Ok(SignerPolicy::Reject),
+            Some("flag") | None => Ok(SignerPolicy::Flag),
+            Some(policy) => Err(anyhow!("Unknown signer_policy: {}", policy)),
+        }
+    }
+}
+
+/// How a reading with the same `sensor_id` and `reading_id` as a stored one, or an annotation
+/// with the same `jti` as a stored one, is handled
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicatePolicy {
+    /// The duplicate is not stored and kept as a dead letter instead
+    Reject,
+    /// The duplicate replaces the stored record
+    Replace,
+    /// The duplicate is dropped
+    KeepFirst,
+}
+
+impl DuplicatePolicy {
+    /// KeepFirst is used if no policy is configured, unknown policies are an error
+    pub fn from_config(policy: Option<&str>) -> Result<Self> {
+        match policy {
+            Some("reject") => Ok(DuplicatePolicy::Reject),
+            Some("replace") => Ok(DuplicatePolicy::Replace),
+            Some("keep_first") | None => Ok(DuplicatePolicy::KeepFirst),
+            Some(policy) => Err(anyhow!("Unknown duplicate_policy: {}", policy)),
+        }
+    }
+}
+
+/// Error for records refused by `DuplicatePolicy::Reject`
+#[derive(Debug)]
+pub struct DuplicateRecord(String);
+
+impl fmt::Display for DuplicateRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Duplicate {}", self.0)
+    }
+}
+
+impl std::error::Error for DuplicateRecord {}
+
+/// Counters for messages that were altered or dropped on ingestion
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct IngestStats {
+    pub readings_flagged: u64,
+    pub readings_rejected: u64,
+    pub readings_unverified: u64,
+    pub readings_duplicate: u64,
+    pub annotations_duplicate: u64,
+    pub journal_errors: u64,
+}
+
+impl IngestStats {
+    pub fn new() -> Self {
+        IngestStats::default()
+    }
+}
+
+/// Readings that were flagged as `signer_mismatch` or `unverified` by `check_signer` are not
+/// verified
+pub fn is_verified(reading: &Reading) -> bool {
+    !reading.metadata.signer_mismatch && !reading.metadata.unverified
+}
+
+/// Compares the signer of a reading with its claimed `sensor_id`. Returns false if the reading
+/// should be dropped, mismatching readings are otherwise flagged in their metadata. Unsigned
+/// readings, such as those of PSK holders, have no signer to compare against and are handled the
+/// same way, flagged as `unverified` instead.
+pub fn check_signer(reading: &mut Reading, policy: SignerPolicy, stats: &mut IngestStats) -> bool {
+    if policy == SignerPolicy::Off {
+        return true
+    }
+
+    let verified = match &reading.metadata.publisher {
+        Some(publisher) => publisher.eq_ignore_ascii_case(&reading.sensor_id.0),
+        None => false
+    };
+
+    if verified {
+        return true
+    }
+
+    match policy {
+        SignerPolicy::Reject => {
+            stats.readings_rejected += 1;
+            false
+        },
+        _ if reading.metadata.publisher.is_none() => {
+            reading.metadata.unverified = true;
+            stats.readings_unverified += 1;
+            true
+        },
+        _ => {
+            reading.metadata.signer_mismatch = true;
+            stats.readings_flagged += 1;
+            true
+        }
+    }
+}
+
+/// Decodes received packets and stores the resulting records. Packets that can't be decoded, or
+/// that are rejected as duplicates, are kept in the dead letter store. If a journal is set, every
+/// received packet is appended to it before being processed.
+pub struct MessageIngestor {
+    decoder: MessageDecoder,
+    signer_policy: SignerPolicy,
+    duplicate_policy: DuplicatePolicy,
+    annotation_store: Arc<Mutex<dyn AnnotationStorage>>,
+    reading_store: Arc<Mutex<dyn ReadingStorage>>,
+    dead_letter_store: Arc<Mutex<DeadLetterStore>>,
+    stats: Arc<Mutex<IngestStats>>,
+    journal: Option<Mutex<Journal>>,
+}
+
+impl MessageIngestor {
+    pub fn new(
+        signer_policy: SignerPolicy,
+        duplicate_policy: DuplicatePolicy,
+        annotation_store: Arc<Mutex<dyn AnnotationStorage>>,
+        reading_store: Arc<Mutex<dyn ReadingStorage>>,
+        dead_letter_store: Arc<Mutex<DeadLetterStore>>,
+        stats: Arc<Mutex<IngestStats>>,
+        journal: Option<Journal>,
+    ) -> Self {
+        MessageIngestor {
+            decoder: MessageDecoder::new(),
+            signer_policy,
+            duplicate_policy,
+            annotation_store,
+            reading_store,
+            dead_letter_store,
+            stats,
+            journal: journal.map(Mutex::new)
+        }
+    }
+
+    /// Journals and processes a received packet. A packet that can't be journaled is still
+    /// processed, the failure is logged and counted in `journal_errors` instead, as the packet
+    /// would otherwise be lost.
+    pub fn ingest(&self, msg: &RawMessage) -> Result<()> {
+        if let Some(journal) = &self.journal {
+            if let Err(e) = lock(journal).append(msg) {
+                println!("Error journaling message from {}: {}", msg.metadata.link, e);
+                lock(&self.stats).journal_errors += 1;
+            }
+        }
+        self.process(msg)
+    }
+
+    /// Processes every entry of a journal file again, without appending them to the journal.
+    /// Entries received before the age limit of the retention policy are skipped, as are entries
+    /// that fail to be stored, as they are on live ingestion. Returns the number of entries
+    /// replayed and the number of those that failed.
+    pub fn replay<P: AsRef<Path>>(&self, path: P, retention: &RetentionPolicy) -> Result<(usize, usize)> {
+        let cutoff = retention.cutoff(now_millis()).unwrap_or(0);
+        let msgs = Journal::read(path)?;
+
+        let mut replayed = 0;
+        let mut failed = 0;
+        for msg in msgs.iter().filter(|msg| msg.metadata.received_at >= cutoff) {
+            replayed += 1;
+            if let Err(e) = self.process(msg) {
+                println!("Error storing message from {}: {}", msg.metadata.link, e);
+                failed += 1;
+            }
+        }
+        Ok((replayed, failed))
+    }
+
+    fn process(&self, msg: &RawMessage) -> Result<()> {
+        let error = match self.decoder.decode(msg) {
+            Ok(payload) => match self.store_payload(payload) {
+                Err(e) if e.is::<DuplicateRecord>() => e,
+                res => return res
+            },
+            Err(e) => {
+                println!("Error deserializing message from {}: {}", msg.metadata.link, e);
+                e
+            }
+        };
+
+        let mut dead_letter_store = lock(&self.dead_letter_store);
+        let id = dead_letter_store.insert(msg, error.to_string())?;
+        println!("Stored as dead letter {}\n", id);
+        Ok(())
+    }
+
+    /// Decodes a dead letter again, storing the record and dropping the dead letter if it succeeds
+    pub fn reprocess(&self, id: u64) -> Result<()> {
+        let msg = lock(&self.dead_letter_store)
+            .get(id)?
+            .to_raw_message()?;
+
+        match self.decoder.decode(&msg) {
+            Ok(payload) => {
+                self.store_payload(payload)?;
+                lock(&self.dead_letter_store).remove(id)?;
+                Ok(())
+            },
+            Err(e) => {
+                lock(&self.dead_letter_store).set_error(id, e.to_string())?;
+                Err(e)
+            }
+        }
+    }
+
+    fn store_payload(&self, payload: ChannelPayload) -> Result<()> {
+        match payload {
+            ChannelPayload::Reading(mut reading) => {
+                println!("Got a new reading message");
+                let accepted = {
+                    let mut stats = lock(&self.stats);
+                    check_signer(&mut reading, self.signer_policy, &mut stats)
+                };
+                if !accepted {
+                    println!("Rejected reading {:?}, sensor_id could not be verified against the signer\n", reading.get_reading_id());
+                    return Ok(())
+                }
+
+                let sensor_id = reading.get_sensor_id().clone();
+                println!("Storing reading: {}", serde_json::to_string(&reading)?);
+                let mut reading_store = lock(&self.reading_store);
+                let stored = reading_store.get_reading(&sensor_id, reading.get_reading_id()).map(is_verified);
+                if let Some(stored_verified) = stored {
+                    lock(&self.stats).readings_duplicate += 1;
+                    let duplicate = format!("reading {} from sensor {}", reading.get_reading_id().0, sensor_id.0);
+                    // Flagged and unverified readings may come from anyone, so they never take the
+                    // place of a verified reading, and a verified reading always takes theirs
+                    let policy = match (stored_verified, is_verified(&reading)) {
+                        (false, true) => DuplicatePolicy::Replace,
+                        (true, false) if self.duplicate_policy == DuplicatePolicy::Replace => DuplicatePolicy::KeepFirst,
+                        _ => self.duplicate_policy
+                    };
+                    match policy {
+                        DuplicatePolicy::Reject => return Err(DuplicateRecord(duplicate).into()),
+                        DuplicatePolicy::KeepFirst => {
+                            println!("Dropped duplicate {}\n", duplicate);
+                            return Ok(())
+                        },
+                        DuplicatePolicy::Replace => {
+                            println!("Replacing duplicate {}", duplicate);
+                            reading_store.remove(&sensor_id, reading.get_reading_id())?;
+                        }
+                    }
+                }
+                reading_store.insert(&sensor_id, reading)?;
+                println!("Stored\n");
+            },
+            ChannelPayload::Annotation(annotation) => {
+                println!("Got a new annotation message");
+                let reading_id = annotation.get_reading_id().clone();
+                println!("Storing annotation: {}", serde_json::to_string(&annotation)?);
+                let mut annotation_store = lock(&self.annotation_store);
+                let jti = annotation.get_annotation().payload.jti.clone();
+                let publisher = annotation.get_metadata().publisher.clone();
+                if annotation_store.contains(&jti, publisher.as_deref()) {
+                    lock(&self.stats).annotations_duplicate += 1;
+                    let duplicate = format!("annotation {}", jti);
+                    match self.duplicate_policy {
+                        DuplicatePolicy::Reject => return Err(DuplicateRecord(duplicate).into()),
+                        DuplicatePolicy::KeepFirst => {
+                            println!("Dropped duplicate {}\n", duplicate);
+                            return Ok(())
+                        },
+                        DuplicatePolicy::Replace => {
+                            println!("Replacing duplicate {}", duplicate);
+                            annotation_store.remove(&jti, publisher.as_deref())?;
+                        }
+                    }
+                }
+                annotation_store.insert(&reading_id, annotation)?;
+                println!("Stored\n");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{AlvariumAnnotation, Annotation, MessageMetadata, ReadingId, SensorId};
+    use crate::store::{AnnotationStore, ReadingStore};
+    use serde_json::json;
+
+    struct Stores {
+        readings: Arc<Mutex<ReadingStore>>,
+        annotations: Arc<Mutex<AnnotationStore>>,
+        dead_letters: Arc<Mutex<DeadLetterStore>>,
+    }
+
+    fn ingestor(duplicate_policy: DuplicatePolicy) -> (MessageIngestor, Stores) {
+        let stores = Stores {
+            readings: Arc::new(Mutex::new(ReadingStore::new())),
+            annotations: Arc::new(Mutex::new(AnnotationStore::new())),
+            dead_letters: Arc::new(Mutex::new(DeadLetterStore::new())),
+        };
+        let ingestor = MessageIngestor::new(
+            SignerPolicy::Flag,
+            duplicate_policy,
+            stores.annotations.clone(),
+            stores.readings.clone(),
+            stores.dead_letters.clone(),
+            Arc::new(Mutex::new(IngestStats::new())),
+            None
+        );
+        (ingestor, stores)
+    }
+
+    fn raw_message(link: &str, publisher: Option<&str>, kind: &str, body: serde_json::Value) -> RawMessage {
+        RawMessage {
+            metadata: MessageMetadata::new(link.to_string(), publisher.map(String::from), &[]),
+            masked_payload: serde_json::to_vec(&json!({ "type": kind, "version": 1, "body": body })).unwrap(),
+        }
+    }
+
+    /// A reading of `sensor`, verified if `sensor` published it
+    fn reading(link: &str, publisher: Option<&str>) -> RawMessage {
+        let reading = Reading::new()
+            .with_sensor_id(SensorId("sensor".to_string()))
+            .with_reading_id(ReadingId("01".to_string()));
+        raw_message(link, publisher, "reading", serde_json::to_value(reading).unwrap())
+    }
+
+    fn annotation(link: &str, publisher: &str) -> RawMessage {
+        let mut payload = AlvariumAnnotation::default();
+        payload.payload.jti = "jti".to_string();
+        let annotation = Annotation::new()
+            .with_reading_id(ReadingId("01".to_string()))
+            .with_annotation(payload);
+        raw_message(link, Some(publisher), "annotation", serde_json::to_value(annotation).unwrap())
+    }
+
+    fn stored_reading(stores: &Stores) -> Option<String> {
+        let mut readings = lock(&stores.readings);
+        assert!(readings.get(&SensorId("sensor".to_string())).map_or(0, |readings| readings.len()) <= 1);
+        readings.get_reading(&SensorId("sensor".to_string()), &ReadingId("01".to_string()))
+            .map(|reading| reading.get_metadata().link.clone())
+    }
+
+    fn stored_annotations(stores: &Stores) -> Vec<String> {
+        lock(&stores.annotations).get(&ReadingId("01".to_string())).unwrap()
+            .into_iter()
+            .map(|annotation| annotation.get_metadata().link.clone())
+            .collect()
+    }
+
+    fn dead_letters(stores: &Stores) -> usize {
+        lock(&stores.dead_letters).ids().len()
+    }
+
+    #[test]
+    fn keep_first() {
+        let (ingestor, stores) = ingestor(DuplicatePolicy::KeepFirst);
+        ingestor.ingest(&reading("a:1", Some("sensor"))).unwrap();
+        ingestor.ingest(&reading("a:2", Some("sensor"))).unwrap();
+        ingestor.ingest(&reading("a:3", Some("spoofer"))).unwrap();
+        assert_eq!(stored_reading(&stores).as_deref(), Some("a:1"));
+        assert_eq!(dead_letters(&stores), 0);
+    }
+
+    #[test]
+    fn keep_first_gives_way_to_a_verified_reading() {
+        let (ingestor, stores) = ingestor(DuplicatePolicy::KeepFirst);
+        ingestor.ingest(&reading("a:1", Some("spoofer"))).unwrap();
+        ingestor.ingest(&reading("a:2", None)).unwrap();
+        assert_eq!(stored_reading(&stores).as_deref(), Some("a:1"));
+
+        ingestor.ingest(&reading("a:3", Some("sensor"))).unwrap();
+        ingestor.ingest(&reading("a:4", Some("spoofer"))).unwrap();
+        assert_eq!(stored_reading(&stores).as_deref(), Some("a:3"));
+    }
+
+    #[test]
+    fn replace() {
+        let (ingestor, stores) = ingestor(DuplicatePolicy::Replace);
+        ingestor.ingest(&reading("a:1", Some("sensor"))).unwrap();
+        ingestor.ingest(&reading("a:2", Some("sensor"))).unwrap();
+        assert_eq!(stored_reading(&stores).as_deref(), Some("a:2"));
+    }
+
+    #[test]
+    fn replace_never_drops_a_verified_reading_for_a_spoofed_one() {
+        let (ingestor, stores) = ingestor(DuplicatePolicy::Replace);
+        ingestor.ingest(&reading("a:1", Some("spoofer"))).unwrap();
+        ingestor.ingest(&reading("a:2", Some("sensor"))).unwrap();
+        ingestor.ingest(&reading("a:3", Some("spoofer"))).unwrap();
+        ingestor.ingest(&reading("a:4", None)).unwrap();
+        assert_eq!(stored_reading(&stores).as_deref(), Some("a:2"));
+        assert_eq!(dead_letters(&stores), 0);
+    }
+
+    #[test]
+    fn reject() {
+        let (ingestor, stores) = ingestor(DuplicatePolicy::Reject);
+        ingestor.ingest(&reading("a:1", Some("sensor"))).unwrap();
+        ingestor.ingest(&reading("a:2", Some("sensor"))).unwrap();
+        ingestor.ingest(&reading("a:3", Some("spoofer"))).unwrap();
+        assert_eq!(stored_reading(&stores).as_deref(), Some("a:1"));
+        assert_eq!(dead_letters(&stores), 2);
+    }
+
+    #[test]
+    fn reject_gives_way_to_a_verified_reading() {
+        let (ingestor, stores) = ingestor(DuplicatePolicy::Reject);
+        ingestor.ingest(&reading("a:1", Some("spoofer"))).unwrap();
+        ingestor.ingest(&reading("a:2", Some("sensor"))).unwrap();
+        assert_eq!(stored_reading(&stores).as_deref(), Some("a:2"));
+        assert_eq!(dead_letters(&stores), 0);
+    }
+
+    #[test]
+    fn annotations_are_unique_per_publisher() {
+        for policy in [DuplicatePolicy::KeepFirst, DuplicatePolicy::Replace, DuplicatePolicy::Reject].iter() {
+            let (ingestor, stores) = ingestor(*policy);
+            ingestor.ingest(&annotation("a:1", "squatter")).unwrap();
+            ingestor.ingest(&annotation("a:2", "host")).unwrap();
+            assert_eq!(stored_annotations(&stores), vec!["a:1", "a:2"]);
+
+            ingestor.ingest(&annotation("a:3", "host")).unwrap();
+            let expected = match policy {
+                DuplicatePolicy::Replace => vec!["a:1", "a:3"],
+                _ => vec!["a:1", "a:2"]
+            };
+            assert_eq!(stored_annotations(&stores), expected);
+            assert_eq!(dead_letters(&stores), if *policy == DuplicatePolicy::Reject { 1 } else { 0 });
+        }
+    }
+}
@@ -1,68 +1,230 @@
-use anyhow::Result;
-use crate::streams::ChannelAuthor;
-use crate::store::{AnnotationStore, ReadingStore};
+use anyhow::{Result, anyhow};
+use crate::store::lock;
+use crate::streams::{AuthorHandle, MessageIngestor};
+use crate::models::{now_millis, RawMessage};
+use hyper::{Client, Uri};
+use serde::Serialize;
 use std::{
     sync::{Arc, Mutex},
-    thread,
-    time::Duration
+    time::{Duration, Instant}
 };
+use tokio::{
+    net::TcpStream,
+    time::{delay_for, timeout}
+};
+
+const RESTART_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// A worker that ran this long before failing is considered healthy again and restarts quickly
+const HEALTHY_RUN_TIME: Duration = Duration::from_secs(60);
+const NODE_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Polling runs at `min_interval` while messages keep arriving, and backs off by `backoff_factor`
+/// up to `max_interval` while the channel is idle or the node fails
+#[derive(Clone, Copy, Debug)]
+pub struct PollingConfig {
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    pub backoff_factor: f64,
+}
+
+impl PollingConfig {
+    pub fn new(min_interval_ms: u64, max_interval_ms: u64, backoff_factor: f64) -> Self {
+        PollingConfig {
+            min_interval: Duration::from_millis(min_interval_ms),
+            max_interval: Duration::from_millis(std::cmp::max(min_interval_ms, max_interval_ms)),
+            backoff_factor: if backoff_factor < 1.0 { 1.0 } else { backoff_factor },
+        }
+    }
+
+    fn next_interval(&self, current: Duration, got_messages: bool) -> Duration {
+        if got_messages {
+            return self.min_interval
+        }
+        let next = Duration::from_secs_f64(current.as_secs_f64() * self.backoff_factor);
+        std::cmp::min(std::cmp::max(next, self.min_interval), self.max_interval)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetrieverState {
+    Starting,
+    Running,
+    Degraded,
+    Restarting,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RetrieverStatus {
+    pub state: RetrieverState,
+    pub restarts: u64,
+    pub consecutive_errors: u64,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<u64>,
+    pub current_interval_ms: u64,
+    pub last_successful_fetch_at: Option<u64>,
+}
 
+impl RetrieverStatus {
+    pub fn new() -> Self {
+        RetrieverStatus {
+            state: RetrieverState::Starting,
+            restarts: 0,
+            consecutive_errors: 0,
+            last_error: None,
+            last_error_at: None,
+            current_interval_ms: 0,
+            last_successful_fetch_at: None,
+        }
+    }
 
+    fn record_error(&mut self, state: RetrieverState, error: String) {
+        self.state = state;
+        self.last_error = Some(error);
+        self.last_error_at = Some(now_millis());
+    }
+}
 
 pub struct MessageRetriever {
-    author: Arc<Mutex<ChannelAuthor>>,
-    annotation_store: Arc<Mutex<AnnotationStore>>,
-    reading_store: Arc<Mutex<ReadingStore>>,
+    author: AuthorHandle,
+    ingestor: Arc<MessageIngestor>,
+    node: String,
+    polling: PollingConfig,
+    status: Arc<Mutex<RetrieverStatus>>,
 }
 
 impl MessageRetriever {
     pub fn new(
-        author: Arc<Mutex<ChannelAuthor>>,
-        annotation_store: Arc<Mutex<AnnotationStore>>,
-        reading_store: Arc<Mutex<ReadingStore>>,
+        author: AuthorHandle,
+        ingestor: Arc<MessageIngestor>,
+        node: &str,
+        polling: PollingConfig,
     ) -> Self {
         MessageRetriever {
             author,
-            annotation_store,
-            reading_store
+            ingestor,
+            node: node.to_string(),
+            polling,
+            status: Arc::new(Mutex::new(RetrieverStatus::new()))
         }
     }
 
+    pub fn get_status(&self) -> Arc<Mutex<RetrieverStatus>> {
+        self.status.clone()
+    }
+
+    /// Spawns the retrieval task under a supervisor task, which restarts the retrieval task with
+    /// an exponential backoff if it ever panics
     pub fn start(retriever: Self) -> Result<()> {
-        println!("Retrieval thread spawning. Searching for new messages...");
-        thread::spawn(move || {
+        println!("Retrieval task spawning. Searching for new messages...");
+        let retriever = Arc::new(retriever);
+        tokio::spawn(async move {
+            let mut backoff = RESTART_BACKOFF_MIN;
             loop {
-                Self::handle_messages(&retriever);
-                thread::sleep(Duration::from_millis(100))
+                let worker = retriever.clone();
+                let started = Instant::now();
+                let panic = match tokio::spawn(async move { worker.run().await }).await {
+                    Ok(_) => return,
+                    Err(e) => e.to_string()
+                };
+
+                if started.elapsed() >= HEALTHY_RUN_TIME {
+                    backoff = RESTART_BACKOFF_MIN;
+                }
+
+                println!("Retrieval task failed: {}. Restarting in {}s", panic, backoff.as_secs());
+                retriever.update_status(|status| {
+                    status.restarts += 1;
+                    status.record_error(RetrieverState::Restarting, panic);
+                });
+
+                delay_for(backoff).await;
+                backoff = std::cmp::min(backoff * 2, RESTART_BACKOFF_MAX);
             }
         });
         Ok(())
     }
 
-    fn handle_messages(&self) {
-        //TODO: Handle all panics here
-        let mut author = self.author.lock().unwrap();
-        let msgs = author.get_next_msgs().unwrap();
-
-        for (reading, annotation) in msgs {
-            println!("Got a new {} message", if reading.is_some() {"reading"} else {"annotation"});
-
-            if reading.is_some() {
-                let reading = reading.unwrap();
-                let sensor_id = reading.get_sensor_id().clone();
-                println!("Storing reading: {}", serde_json::to_string(&reading).unwrap());
-                let mut reading_store = self.reading_store.lock().unwrap();
-                reading_store.insert(&sensor_id, reading).unwrap();
-                println!("Stored\n");
-            } else {
-                let annotation = annotation.unwrap();
-                let reading_id = annotation.get_reading_id().clone();
-                println!("Storing annotation: {}", serde_json::to_string(&annotation).unwrap());
-                let mut annotation_store = self.annotation_store.lock().unwrap();
-                annotation_store.insert(&reading_id, annotation).unwrap();
-                println!("Stored\n");
+    async fn run(&self) {
+        let mut interval = self.polling.min_interval;
+        loop {
+            match self.fetch().await {
+                Ok(msgs) => {
+                    interval = self.polling.next_interval(interval, !msgs.is_empty());
+                    let failed = self.handle_messages(msgs);
+
+                    // Store failures are reported, but the fetch itself succeeded so polling
+                    // carries on at the same pace
+                    self.update_status(|status| {
+                        status.state = RetrieverState::Running;
+                        status.consecutive_errors = 0;
+                        status.last_successful_fetch_at = Some(now_millis());
+                        status.current_interval_ms = interval.as_millis() as u64;
+                        if failed > 0 {
+                            status.record_error(RetrieverState::Degraded, format!("Failed to store {} messages", failed));
+                        }
+                    })
+                },
+                Err(e) => {
+                    println!("Error retrieving messages: {}", e);
+                    interval = self.polling.next_interval(interval, false);
+                    self.update_status(|status| {
+                        status.consecutive_errors += 1;
+                        status.record_error(RetrieverState::Degraded, e.to_string());
+                        status.current_interval_ms = interval.as_millis() as u64;
+                    });
+                }
             }
+            delay_for(interval).await
         }
     }
 
-}
\ No newline at end of file
+    /// The Streams client returns no messages, rather than an error, when the node fails. An empty
+    /// fetch is only taken as an idle channel once the node is confirmed to be up.
+    async fn fetch(&self) -> Result<Vec<RawMessage>> {
+        let msgs = self.author.get_next_msgs().await?;
+        if msgs.is_empty() {
+            check_node(&self.node).await
+                .map_err(|e| anyhow!("Node {} unavailable: {}", self.node, e))?;
+        }
+        Ok(msgs)
+    }
+
+    /// Returns the number of messages that could not be stored
+    fn handle_messages(&self, msgs: Vec<RawMessage>) -> usize {
+        // The author task only handles the fetch itself, decoding and storing happens here so
+        // other author requests are not held up by it
+        let mut failed = 0;
+        for msg in msgs {
+            if let Err(e) = self.ingestor.ingest(&msg) {
+                println!("Error storing message from {}: {}", msg.metadata.link, e);
+                failed += 1;
+            }
+        }
+        failed
+    }
+
+    fn update_status<F: FnOnce(&mut RetrieverStatus)>(&self, update: F) {
+        update(&mut lock(&self.status))
+    }
+}
+
+/// Plain http nodes are asked for their `/health`, which fails while the node is down or not
+/// synced. Other nodes are only checked for a connection, as there is no TLS client at hand.
+async fn check_node(node: &str) -> Result<()> {
+    let uri: Uri = node.parse()?;
+    let host = uri.host().ok_or_else(|| anyhow!("No host in node url"))?;
+
+    if uri.scheme_str() == Some("http") {
+        let health: Uri = format!("{}/health", node.trim_end_matches('/')).parse()?;
+        let response = timeout(NODE_CHECK_TIMEOUT, Client::new().get(health)).await??;
+        if !response.status().is_success() {
+            return Err(anyhow!("Health check returned {}", response.status()));
+        }
+    } else {
+        let port = uri.port_u16().unwrap_or(443);
+        timeout(NODE_CHECK_TIMEOUT, TcpStream::connect((host, port))).await??;
+    }
+    Ok(())
+}
@@ -1,5 +1,13 @@
 pub mod author;
+pub mod author_handle;
+pub mod author_state;
+pub mod decoder;
+pub mod ingest;
 pub mod message_retriever;
 
 pub use author::*;
+pub use author_handle::*;
+pub use author_state::*;
+pub use decoder::*;
+pub use ingest::*;
 pub use message_retriever::*;
\ No newline at end of file